@@ -172,7 +172,10 @@ fn emit_messages_default(
                             // FIXME(#115869): support -Z ignore-directory-in-diagnostics-source-blocks
                             source_map.ensure_source_file_source_present(&file);
                             (
-                                format!("{}", source_map.filename_for_diagnostics(&file.name)),
+                                format!(
+                                    "{}",
+                                    source_map.filename_for_diagnostics(&file.displayed_name())
+                                ),
                                 source_string(file.clone(), &line),
                                 line.line_index,
                                 line.annotations,
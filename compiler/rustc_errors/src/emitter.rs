@@ -1413,7 +1413,9 @@ fn emit_message_default(
                                 line_idx,
                                 &format!(
                                     "{}:{}:{}",
-                                    sm.filename_for_diagnostics(&annotated_file.file.name),
+                                    sm.filename_for_diagnostics(
+                                        &annotated_file.file.displayed_name()
+                                    ),
                                     sm.doctest_offset_line(
                                         &annotated_file.file.name,
                                         line.line_index
@@ -1469,7 +1471,7 @@ fn emit_message_default(
                         buffer_msg_line_offset,
                         &format!(
                             "{}:{}:{}",
-                            sm.filename_for_diagnostics(&loc.file.name),
+                            sm.filename_for_diagnostics(&loc.file.displayed_name()),
                             sm.doctest_offset_line(&loc.file.name, loc.line),
                             loc.col.0 + 1,
                         ),
@@ -1483,7 +1485,7 @@ fn emit_message_default(
                         0,
                         &format!(
                             "{}:{}:{}: ",
-                            sm.filename_for_diagnostics(&loc.file.name),
+                            sm.filename_for_diagnostics(&loc.file.displayed_name()),
                             sm.doctest_offset_line(&loc.file.name, loc.line),
                             loc.col.0 + 1,
                         ),
@@ -1511,12 +1513,15 @@ fn emit_message_default(
                     };
                     format!(
                         "{}:{}{}",
-                        sm.filename_for_diagnostics(&annotated_file.file.name),
+                        sm.filename_for_diagnostics(&annotated_file.file.displayed_name()),
                         sm.doctest_offset_line(&annotated_file.file.name, first_line.line_index),
                         col
                     )
                 } else {
-                    format!("{}", sm.filename_for_diagnostics(&annotated_file.file.name))
+                    format!(
+                        "{}",
+                        sm.filename_for_diagnostics(&annotated_file.file.displayed_name())
+                    )
                 };
                 buffer.append(buffer_msg_line_offset + 1, &loc, Style::LineAndColumn);
                 for _ in 0..max_line_num_len {
@@ -1772,7 +1777,8 @@ fn emit_suggestion_default(
                 if loc.file.name != sm.span_to_filename(span) && loc.file.name.is_real() {
                     let arrow = "--> ";
                     buffer.puts(row_num - 1, 0, arrow, Style::LineNumber);
-                    let filename = sm.filename_for_diagnostics(&loc.file.name);
+                    let displayed_name = loc.file.displayed_name();
+                    let filename = sm.filename_for_diagnostics(&displayed_name);
                     let offset = sm.doctest_offset_line(&loc.file.name, loc.line);
                     let message = format!("{}:{}:{}", filename, offset, loc.col.0 + 1);
                     if row_num == 2 {
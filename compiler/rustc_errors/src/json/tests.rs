@@ -26,6 +26,17 @@ struct SpanTestData {
     pub column_end: u32,
 }
 
+#[derive(Deserialize, Debug, PartialEq, Eq)]
+struct RenderedTestData {
+    rendered: String,
+    spans: Vec<FileNameTestData>,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Eq)]
+struct FileNameTestData {
+    file_name: String,
+}
+
 struct Shared<T> {
     data: Arc<Mutex<T>>,
 }
@@ -40,36 +51,42 @@ fn flush(&mut self) -> io::Result<()> {
     }
 }
 
+/// Emits an error at `span` with a `JsonEmitter` using `sm`, and returns the JSON output.
+fn emit_json(sm: Lrc<SourceMap>, span: Span) -> String {
+    let fallback_bundle =
+        crate::fallback_fluent_bundle(vec![crate::DEFAULT_LOCALE_RESOURCE], false);
+
+    let output = Arc::new(Mutex::new(Vec::new()));
+    let je = JsonEmitter::new(
+        Box::new(Shared { data: output.clone() }),
+        None,
+        sm,
+        None,
+        fallback_bundle,
+        true,
+        HumanReadableErrorType::Short(ColorConfig::Never),
+        None,
+        false,
+        false,
+        TerminalUrl::No,
+    );
+
+    let handler = Handler::with_emitter(Box::new(je));
+    handler.span_err(span, "foo");
+
+    let bytes = output.lock().unwrap();
+    str::from_utf8(&bytes).unwrap().to_owned()
+}
+
 /// Test the span yields correct positions in JSON.
 fn test_positions(code: &str, span: (u32, u32), expected_output: SpanTestData) {
     rustc_span::create_default_session_globals_then(|| {
         let sm = Lrc::new(SourceMap::new(FilePathMapping::empty()));
         sm.new_source_file(Path::new("test.rs").to_owned().into(), code.to_owned());
-        let fallback_bundle =
-            crate::fallback_fluent_bundle(vec![crate::DEFAULT_LOCALE_RESOURCE], false);
-
-        let output = Arc::new(Mutex::new(Vec::new()));
-        let je = JsonEmitter::new(
-            Box::new(Shared { data: output.clone() }),
-            None,
-            sm,
-            None,
-            fallback_bundle,
-            true,
-            HumanReadableErrorType::Short(ColorConfig::Never),
-            None,
-            false,
-            false,
-            TerminalUrl::No,
-        );
 
         let span = Span::with_root_ctxt(BytePos(span.0), BytePos(span.1));
-        let handler = Handler::with_emitter(Box::new(je));
-        handler.span_err(span, "foo");
-
-        let bytes = output.lock().unwrap();
-        let actual_output = str::from_utf8(&bytes).unwrap();
-        let actual_output: TestData = serde_json::from_str(actual_output).unwrap();
+        let actual_output = emit_json(sm, span);
+        let actual_output: TestData = serde_json::from_str(&actual_output).unwrap();
         let spans = actual_output.spans;
         assert_eq!(spans.len(), 1);
 
@@ -77,6 +94,26 @@ fn test_positions(code: &str, span: (u32, u32), expected_output: SpanTestData) {
     })
 }
 
+/// Test that the rendered diagnostic shows the display name set for a file, while the JSON span
+/// keeps its real name.
+#[test]
+fn display_name() {
+    rustc_span::create_default_session_globals_then(|| {
+        let sm = Lrc::new(SourceMap::new(FilePathMapping::empty()));
+        let file = sm.new_source_file(
+            Path::new("tmp/gen-1234.rs").to_owned().into(),
+            "fn main() {}".to_owned(),
+        );
+        sm.set_display_name(&file, Path::new("generated.rs").to_owned().into());
+
+        let span = Span::with_root_ctxt(BytePos(3), BytePos(7));
+        let actual_output = emit_json(sm, span);
+        let actual_output: RenderedTestData = serde_json::from_str(&actual_output).unwrap();
+        assert_eq!(actual_output.rendered, "generated.rs:1:4: error: foo\n");
+        assert_eq!(actual_output.spans[0].file_name, "tmp/gen-1234.rs");
+    })
+}
+
 #[test]
 fn empty() {
     test_positions(
@@ -61,6 +61,8 @@ impl<'a> HashStable<StableHashingContext<'a>> for SourceFile {
     fn hash_stable(&self, hcx: &mut StableHashingContext<'a>, hasher: &mut StableHasher) {
         let SourceFile {
             name: _, // We hash the smaller name_hash instead of this
+            // The display name only affects how diagnostics show the file
+            display_name: _,
             name_hash,
             cnum,
             // Do not hash the source as it is not encoded
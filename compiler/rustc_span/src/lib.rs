@@ -677,6 +677,19 @@ pub fn parent_callsite(self) -> Option<Span> {
         if !expn_data.is_root() { Some(expn_data.call_site) } else { None }
     }
 
+    /// Returns the number of macro expansions between `self` and the code the user wrote, i.e. how
+    /// often [`Span::parent_callsite`] can be applied. This is 0 for a span that is not from an
+    /// expansion.
+    pub fn expansion_depth(self) -> usize {
+        let mut depth = 0;
+        let mut sp = self;
+        while let Some(parent) = sp.parent_callsite() {
+            depth += 1;
+            sp = parent;
+        }
+        depth
+    }
+
     /// Walk down the expansion ancestors to find a span that's contained within `outer`.
     ///
     /// The span returned by this method may have a different [`SyntaxContext`] as `outer`.
@@ -797,6 +810,15 @@ pub fn desugaring_kind(self) -> Option<DesugaringKind> {
         }
     }
 
+    /// Returns the name of the macro whose expansion created this span, or `None` if this span
+    /// is not from a macro expansion.
+    pub fn expn_callee_name(self) -> Option<Symbol> {
+        match self.ctxt().outer_expn_data().kind {
+            ExpnKind::Macro(_, name) => Some(name),
+            _ => None,
+        }
+    }
+
     /// Checks if a span is "internal" to a macro in which `unsafe`
     /// can be used without triggering the `unsafe_code` lint.
     /// (that is, a macro marked with `#[allow_internal_unsafe]`).
@@ -838,6 +860,15 @@ pub fn split_at(self, pos: u32) -> (Span, Span) {
         )
     }
 
+    /// Returns a span with both `lo` and `hi` moved by `delta` bytes, keeping the context and
+    /// parent. A shift that would move an endpoint below zero clamps it at `BytePos(0)` instead
+    /// of wrapping around, and likewise at `u32::MAX` for a shift past the end.
+    pub fn shifted(self, delta: i32) -> Span {
+        let span = self.data();
+        let shift = |pos: BytePos| BytePos(pos.0.saturating_add_signed(delta));
+        Span::new(shift(span.lo), shift(span.hi), span.ctxt, span.parent)
+    }
+
     /// Returns a `Span` that would enclose both `self` and `end`.
     ///
     /// Note that this can also be used to extend the span "backwards":
@@ -1176,6 +1207,19 @@ pub struct NormalizedPos {
     pub diff: u32,
 }
 
+/// The kind of line breaks used in a `SourceFile`, see [`SourceFile::line_ending_style`].
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum LineEnding {
+    /// `\n`
+    Lf,
+    /// `\r\n`
+    CrLf,
+    /// `\r`
+    Cr,
+    /// More than one of the above.
+    Mixed,
+}
+
 #[derive(PartialEq, Eq, Clone, Debug)]
 pub enum ExternalSource {
     /// No external source has to be loaded, since the `SourceFile` represents a local crate.
@@ -1326,6 +1370,9 @@ pub struct SourceFile {
     /// originate from files has names between angle brackets by convention
     /// (e.g., `<anon>`).
     pub name: FileName,
+    /// The name to show for this file in diagnostics instead of `name`, if one has been set
+    /// with [`SourceMap::set_display_name`]. Lookups by name always use `name`.
+    pub display_name: Lock<Option<FileName>>,
     /// The complete source code.
     pub src: Option<Lrc<String>>,
     /// The source code's hash.
@@ -1355,6 +1402,7 @@ impl Clone for SourceFile {
     fn clone(&self) -> Self {
         Self {
             name: self.name.clone(),
+            display_name: Lock::new(self.display_name.borrow().clone()),
             src: self.src.clone(),
             src_hash: self.src_hash,
             external_src: self.external_src.clone(),
@@ -1374,7 +1422,7 @@ impl<S: Encoder> Encodable<S> for SourceFile {
     fn encode(&self, s: &mut S) {
         self.name.encode(s);
         self.src_hash.encode(s);
-        // Do not encode `start_pos` as it's global state for this session.
+        // Do not encode `display_name` or `start_pos` as they're global state for this session.
         self.source_len.encode(s);
 
         // We are always in `Lines` form by the time we reach here.
@@ -1470,6 +1518,7 @@ fn decode(d: &mut D) -> SourceFile {
         let cnum: CrateNum = Decodable::decode(d);
         SourceFile {
             name,
+            display_name: Lock::new(None),
             start_pos: BytePos::from_u32(0),
             source_len,
             src: None,
@@ -1516,6 +1565,7 @@ pub fn new(
 
         Ok(SourceFile {
             name,
+            display_name: Lock::new(None),
             src: Some(Lrc::new(src)),
             src_hash,
             external_src: FreezeLock::frozen(ExternalSource::Unneeded),
@@ -1582,6 +1632,10 @@ fn convert_diffs_to_lines_frozen(&self) {
             _ => unreachable!(),
         }
 
+        debug_assert!(
+            lines.windows(2).all(|pair| pair[0] < pair[1]),
+            "line starts must be strictly increasing"
+        );
         *guard = SourceFileLines::Lines(lines);
 
         FreezeWriteGuard::freeze(guard);
@@ -1679,10 +1733,94 @@ fn get_until_newline(src: &str, begin: usize) -> &str {
         }
     }
 
+    /// Calls `f` with the text of a line, without its trailing newline. Unlike `get_line`, the
+    /// line is borrowed from the source instead of being copied. The line number here is 0-based.
+    /// Returns `None` if the line or the source is not available.
+    pub fn with_line<T>(&self, line_number: usize, f: impl FnOnce(&str) -> T) -> Option<T> {
+        let begin = self.lines().get(line_number)?.to_usize();
+        let with_line = |src: &str| {
+            let rest = &src[begin..];
+            f(rest.split_once('\n').map_or(rest, |(line, _)| line))
+        };
+
+        if let Some(ref src) = self.src {
+            Some(with_line(src))
+        } else {
+            self.external_src.borrow().get_source().map(|src| with_line(src))
+        }
+    }
+
+    /// Returns the kind of line breaks used in the original source of this file. `\r\n` has been
+    /// normalized to `\n` by the time the source is stored, so it is recognized by the recorded
+    /// normalizations. Returns `None` if the file has no line breaks or its source is not
+    /// available.
+    pub fn line_ending_style(&self) -> Option<LineEnding> {
+        // Apart from a removed BOM at position 0, every normalization is a removed `\r` of `\r\n`.
+        let crlf = self.normalized_pos.iter().filter(|np| np.pos != RelativeBytePos(0)).count();
+        let count = |src: &str| (src.matches('\n').count() - crlf, src.matches('\r').count());
+        let (lf, cr) = if let Some(ref src) = self.src {
+            count(src)
+        } else {
+            count(self.external_src.borrow().get_source()?)
+        };
+
+        match (lf > 0, crlf > 0, cr > 0) {
+            (false, false, false) => None,
+            (true, false, false) => Some(LineEnding::Lf),
+            (false, true, false) => Some(LineEnding::CrLf),
+            (false, false, true) => Some(LineEnding::Cr),
+            _ => Some(LineEnding::Mixed),
+        }
+    }
+
+    /// Returns the absolute position of each non-overlapping occurrence of `needle` in the source
+    /// of this file, or an empty vector if the source is not available.
+    pub fn find_all(&self, needle: &str) -> Vec<BytePos> {
+        let find = |src: &str| {
+            src.match_indices(needle)
+                .map(|(offset, _)| self.absolute_position(RelativeBytePos::from_usize(offset)))
+                .collect()
+        };
+        if let Some(ref src) = self.src {
+            find(src)
+        } else {
+            self.external_src.borrow().get_source().map_or_else(Vec::new, |src| find(src))
+        }
+    }
+
+    /// Returns a hash of the (normalized) source of this file that is stable across sessions and
+    /// platforms, or `None` if the source is not available. Unlike `src_hash`, this is cheap to
+    /// compare and matches [`SourceMap::span_content_hash`] for a span covering the whole file.
+    pub fn content_hash(&self) -> Option<Hash64> {
+        let hash = |src: &str| {
+            let mut hasher = StableHasher::new();
+            src.hash(&mut hasher);
+            hasher.finish()
+        };
+        if let Some(ref src) = self.src {
+            Some(hash(src))
+        } else {
+            self.external_src.borrow().get_source().map(|src| hash(src))
+        }
+    }
+
+    /// Returns the absolute position of each `\n` in the source of this file. Unlike the line
+    /// starts in [`SourceFile::lines`], these point at the newlines themselves, and a trailing
+    /// newline is included. Returns an empty vector if the source is not available.
+    pub fn newline_positions(&self) -> Vec<BytePos> {
+        self.find_all("\n")
+    }
+
     pub fn is_real_file(&self) -> bool {
         self.name.is_real()
     }
 
+    /// Returns the name to show for this file in diagnostics, which is `name` unless it has been
+    /// overridden with [`SourceMap::set_display_name`].
+    pub fn displayed_name(&self) -> FileName {
+        self.display_name.borrow().clone().unwrap_or_else(|| self.name.clone())
+    }
+
     #[inline]
     pub fn is_imported(&self) -> bool {
         self.src.is_none()
@@ -1692,6 +1830,49 @@ pub fn count_lines(&self) -> usize {
         self.lines().len()
     }
 
+    /// Returns `true` if two adjacent entries of `lines` are equal. That can only happen if the
+    /// line starts were recorded or decoded incorrectly, and makes `lookup_line` report wrong
+    /// lines.
+    pub fn has_duplicate_line_starts(&self) -> bool {
+        self.lines().windows(2).any(|pair| pair[0] == pair[1])
+    }
+
+    /// Returns the number of `char`s in this file, which is less than its byte length if the
+    /// file contains multi-byte characters.
+    pub fn char_count(&self) -> usize {
+        let extra_bytes: usize =
+            self.multibyte_chars.iter().map(|mbc| mbc.bytes as usize - 1).sum();
+        self.source_len.to_usize() - extra_bytes
+    }
+
+    /// Returns the number of two-, three- and four-byte characters in this file, in that order.
+    pub fn multibyte_histogram(&self) -> (usize, usize, usize) {
+        let count =
+            |bytes: u8| self.multibyte_chars.iter().filter(|mbc| mbc.bytes == bytes).count();
+        (count(2), count(3), count(4))
+    }
+
+    /// Returns the position of the `char_idx`-th (0-based) char in this file, or the end of the
+    /// file if `char_idx` is the file's char count. Returns `None` if `char_idx` is larger than
+    /// that. Only the multi-byte char table is consulted, so the source is not needed.
+    pub fn char_offset_to_bytepos(&self, char_idx: usize) -> Option<BytePos> {
+        if char_idx > self.char_count() {
+            return None;
+        }
+
+        let mut offset = char_idx;
+        for mbc in self.multibyte_chars.iter() {
+            if mbc.pos.to_usize() < offset {
+                // Every character is at least one byte, so we only
+                // count the actual extra bytes.
+                offset += mbc.bytes as usize - 1;
+            } else {
+                break;
+            }
+        }
+        Some(self.absolute_position(RelativeBytePos::from_usize(offset)))
+    }
+
     #[inline]
     pub fn absolute_position(&self, pos: RelativeBytePos) -> BytePos {
         BytePos::from_u32(pos.to_u32() + self.start_pos.to_u32())
@@ -2059,7 +2240,7 @@ fn decode(d: &mut D) -> RelativeBytePos {
 }
 
 // _____________________________________________________________________________
-// Loc, SourceFileAndLine, SourceFileAndBytePos
+// Loc, FullLoc, SourceFileAndLine, SourceFileAndBytePos, LineCol
 //
 
 /// A source code location used for error reporting.
@@ -2075,6 +2256,22 @@ pub struct Loc {
     pub col_display: usize,
 }
 
+/// A [`Loc`] that also records the position it was looked up from, for building spans next to
+/// it without another lookup.
+#[derive(Debug, Clone)]
+pub struct FullLoc {
+    /// Information about the original source.
+    pub file: Lrc<SourceFile>,
+    /// The (1-based) line number.
+    pub line: usize,
+    /// The (0-based) column offset.
+    pub col: CharPos,
+    /// The position that was looked up.
+    pub abs_pos: BytePos,
+    /// The position relative to the start of `file`.
+    pub file_offset: RelativeBytePos,
+}
+
 // Used to be structural records.
 #[derive(Debug)]
 pub struct SourceFileAndLine {
@@ -2088,6 +2285,16 @@ pub struct SourceFileAndBytePos {
     pub pos: BytePos,
 }
 
+/// A line and column within a file, both 0-based, as used by editors for selections. See
+/// [`SourceMap::span_to_selection`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct LineCol {
+    /// Index of line, starting from 0.
+    pub line: usize,
+    /// The (0-based) column offset.
+    pub col: CharPos,
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct LineInfo {
     /// Index of line, starting from 0.
@@ -15,8 +15,10 @@
 use rustc_data_structures::fx::FxHashMap;
 use rustc_data_structures::stable_hasher::{Hash128, Hash64, StableHasher};
 use rustc_data_structures::sync::{IntoDynSyncSend, Lrc, MappedReadGuard, ReadGuard, RwLock};
+use rustc_serialize::{Decodable, Decoder, Encodable, Encoder};
 use std::cmp;
 use std::hash::Hash;
+use std::ops::Range;
 use std::path::{self, Path, PathBuf};
 
 use std::fs;
@@ -90,6 +92,12 @@ pub fn dummy_spanned<T>(t: T) -> Spanned<T> {
     respan(DUMMY_SP, t)
 }
 
+/// Returns the underline for `len` columns starting at column `col`: `col` spaces, a `^` and
+/// `len - 1` `~`s. An empty range is still marked with a single `^`.
+pub fn make_caret(col: usize, len: usize) -> String {
+    format!("{}^{}", " ".repeat(col), "~".repeat(len.saturating_sub(1)))
+}
+
 // _____________________________________________________________________________
 // SourceFile, MultiByteChar, FileName, FileLines
 //
@@ -202,6 +210,15 @@ fn new_from_name(name: &FileName, cnum: CrateNum) -> StableSourceFileId {
     }
 }
 
+/// The name, position and length of a [`SourceFile`], without its source. See
+/// [`SourceMap::file_index`].
+#[derive(Clone, PartialEq, Eq, Debug, Encodable, Decodable)]
+pub struct SourceFileIndexEntry {
+    pub name: FileName,
+    pub start_pos: BytePos,
+    pub source_len: RelativeBytePos,
+}
+
 // _____________________________________________________________________________
 // SourceMap
 //
@@ -282,6 +299,41 @@ pub fn files(&self) -> MappedReadGuard<'_, monotonic::MonotonicVec<Lrc<SourceFil
         ReadGuard::map(self.files.borrow(), |files| &files.source_files)
     }
 
+    /// Returns the name of every `SourceFile`, in the order the files were added.
+    pub fn filenames(&self) -> Vec<FileName> {
+        self.files().iter().map(|sf| sf.name.clone()).collect()
+    }
+
+    /// Returns every `SourceFile` together with the range of `BytePos`s it occupies, in the order
+    /// the files were added. Consecutive ranges are separated by a gap of one byte.
+    pub fn file_ranges(&self) -> Vec<(Lrc<SourceFile>, Range<BytePos>)> {
+        self.files().iter().map(|sf| (sf.clone(), sf.start_pos..sf.end_position())).collect()
+    }
+
+    /// Returns the name, start position and length of every `SourceFile`, in the order the files
+    /// were added. This is enough to tell which file a persisted `BytePos` pointed into.
+    pub fn file_index(&self) -> Vec<SourceFileIndexEntry> {
+        self.files()
+            .iter()
+            .map(|sf| SourceFileIndexEntry {
+                name: sf.name.clone(),
+                start_pos: sf.start_pos,
+                source_len: sf.source_len,
+            })
+            .collect()
+    }
+
+    /// Writes the [`SourceMap::file_index`] to `e`, so that it can be read back with
+    /// [`SourceMap::decode_file_index`]. The sources of the files are not written.
+    pub fn encode_file_index<E: Encoder>(&self, e: &mut E) {
+        self.file_index().encode(e);
+    }
+
+    /// Reads a file index written by [`SourceMap::encode_file_index`].
+    pub fn decode_file_index<D: Decoder>(d: &mut D) -> Vec<SourceFileIndexEntry> {
+        Decodable::decode(d)
+    }
+
     pub fn source_file_by_stable_id(
         &self,
         stable_id: StableSourceFileId,
@@ -367,6 +419,7 @@ pub fn new_imported_source_file(
 
         let source_file = SourceFile {
             name: filename,
+            display_name: Lock::new(None),
             src: None,
             src_hash,
             external_src: FreezeLock::new(ExternalSource::Foreign {
@@ -408,6 +461,12 @@ pub fn lookup_source_file(&self, pos: BytePos) -> Lrc<SourceFile> {
         (*self.files.borrow().source_files)[idx].clone()
     }
 
+    /// Returns `true` if `a` and `b` are in the same `SourceFile`, without computing their lines
+    /// or columns. The position just past the end of a file still counts as part of it.
+    pub fn same_file(&self, a: BytePos, b: BytePos) -> bool {
+        self.lookup_source_file_idx(a) == self.lookup_source_file_idx(b)
+    }
+
     /// Looks up source information about a `BytePos`.
     pub fn lookup_char_pos(&self, pos: BytePos) -> Loc {
         let sf = self.lookup_source_file(pos);
@@ -415,6 +474,89 @@ pub fn lookup_char_pos(&self, pos: BytePos) -> Loc {
         Loc { file: sf, line, col, col_display }
     }
 
+    /// If `pos` is in a doctest, returns its path, 1-based line and 0-based column in the file the
+    /// doctest was extracted from, using [`SourceMap::doctest_offset_line`]. Returns `None` for
+    /// positions in any other kind of file.
+    pub fn lookup_external_pos(&self, pos: BytePos) -> Option<(PathBuf, usize, CharPos)> {
+        let loc = self.lookup_char_pos(pos);
+        match &loc.file.name {
+            FileName::DocTest(path, _) => {
+                let line = self.doctest_offset_line(&loc.file.name, loc.line);
+                Some((path.clone(), line, loc.col))
+            }
+            _ => None,
+        }
+    }
+
+    /// Looks up source information about a `BytePos` like `lookup_char_pos`, but returns an error
+    /// describing the problem instead of panicking or returning a bogus `Loc` if `pos` doesn't
+    /// point into any `SourceFile`.
+    pub fn try_lookup_char_pos(&self, pos: BytePos) -> Result<Loc, String> {
+        let end = self.files().last().map(|sf| sf.end_position());
+        match end {
+            None => Err(format!("cannot look up {pos:?}: the source map is empty")),
+            Some(end) if pos > end => {
+                Err(format!("cannot look up {pos:?}: the last source file ends at {end:?}"))
+            }
+            Some(_) => Ok(self.lookup_char_pos(pos)),
+        }
+    }
+
+    /// Returns the start and end of `sp` as an editor selection `(anchor, active)`, with 0-based
+    /// lines and columns.
+    pub fn span_to_selection(&self, sp: Span) -> (LineCol, LineCol) {
+        let line_col = |pos| {
+            let loc = self.lookup_char_pos(pos);
+            LineCol { line: loc.line.saturating_sub(1), col: loc.col }
+        };
+        (line_col(sp.lo()), line_col(sp.hi()))
+    }
+
+    /// Looks up source information about a `BytePos`, like `lookup_char_pos`, but also returns
+    /// the position itself and its offset from the start of its `SourceFile`.
+    pub fn lookup_full(&self, pos: BytePos) -> FullLoc {
+        let sf = self.lookup_source_file(pos);
+        let file_offset = sf.relative_position(pos);
+        let (line, col) = sf.lookup_file_pos(file_offset);
+        FullLoc { file: sf, line, col, abs_pos: pos, file_offset }
+    }
+
+    /// Returns the `BytePos` of the beginning of the line containing `pos`, without resolving
+    /// its line and column like `lookup_char_pos` does. Returns `None` if the file is empty.
+    pub fn line_begin_pos(&self, pos: BytePos) -> Option<BytePos> {
+        let SourceFileAndLine { sf, line } = self.lookup_line(pos).ok()?;
+        Some(sf.absolute_position(sf.lines()[line]))
+    }
+
+    /// Returns the 1-based column of `pos` as shown by editors, counting chars and expanding tabs
+    /// to the next multiple of `tab_width` columns. If the source is not available, tabs are
+    /// counted as one column. `tab_width` must not be zero.
+    pub fn lookup_editor_col(&self, pos: BytePos, tab_width: usize) -> usize {
+        let prefix = self.span_from_line_start(pos);
+        let col = match self.span_to_snippet_expanded(prefix, tab_width) {
+            Ok(expanded) => expanded.chars().count(),
+            Err(_) => self.lookup_char_pos(pos).col.to_usize(),
+        };
+        col + 1
+    }
+
+    /// Returns the (0-based) byte column and (0-based) `CharPos` column of `pos` within its line.
+    /// The two differ once the line contains multi-byte characters before `pos`.
+    pub fn lookup_cols(&self, pos: BytePos) -> (usize, CharPos) {
+        let sf = self.lookup_source_file(pos);
+        let pos = sf.relative_position(pos);
+        let line_start = sf.lookup_line(pos).map_or(RelativeBytePos(0), |line| sf.lines()[line]);
+        let char_col = sf.bytepos_to_file_charpos(pos) - sf.bytepos_to_file_charpos(line_start);
+        ((pos - line_start).to_usize(), char_col)
+    }
+
+    /// Returns the 0-based index of the line containing `pos` within its `SourceFile`, suitable
+    /// for indexing into its lines. This is one less than the 1-based `Loc::line` shown to users.
+    /// Returns `None` if the file is empty.
+    pub fn lookup_line_index(&self, pos: BytePos) -> Option<usize> {
+        self.lookup_line(pos).ok().map(|SourceFileAndLine { line, .. }| line)
+    }
+
     /// If the corresponding `SourceFile` is empty, does not return a line number.
     pub fn lookup_line(&self, pos: BytePos) -> Result<SourceFileAndLine, Lrc<SourceFile>> {
         let f = self.lookup_source_file(pos);
@@ -434,7 +576,7 @@ pub fn span_to_string(
         let (source_file, lo_line, lo_col, hi_line, hi_col) = self.span_to_location_info(sp);
 
         let file_name = match source_file {
-            Some(sf) => sf.name.display(filename_display_pref).to_string(),
+            Some(sf) => sf.displayed_name().display(filename_display_pref).to_string(),
             None => return "no-location".to_string(),
         };
 
@@ -500,10 +642,49 @@ pub fn span_to_diagnostic_string(&self, sp: Span) -> String {
         self.span_to_string(sp, self.path_mapping.filename_display_for_diagnostics)
     }
 
+    /// Formats the location of the start of the span as `file:line:col`. Unlike
+    /// `span_to_diagnostic_string`, lines of doctests are reported as lines of the file the
+    /// doctest was extracted from, using [`SourceMap::doctest_offset_line`].
+    pub fn span_to_original_string(&self, sp: Span) -> String {
+        let (source_file, lo_line, lo_col, _, _) = self.span_to_location_info(sp);
+        let Some(sf) = source_file else {
+            return "no-location".to_string();
+        };
+
+        let file_name = self.filename_for_diagnostics(&sf.displayed_name()).to_string();
+        let line = self.doctest_offset_line(&sf.name, lo_line);
+        format!("{file_name}:{line}:{lo_col}")
+    }
+
+    /// Formats the span location as `file:line`, or as `file:first-last` if the span covers
+    /// several lines, for output where columns would only be noise.
+    pub fn span_to_line_range_string(&self, sp: Span) -> String {
+        let (source_file, lo_line, _, hi_line, _) = self.span_to_location_info(sp);
+        let Some(sf) = source_file else {
+            return "no-location".to_string();
+        };
+
+        let file_name = self.filename_for_diagnostics(&sf.displayed_name()).to_string();
+        if lo_line == hi_line {
+            format!("{file_name}:{lo_line}")
+        } else {
+            format!("{file_name}:{lo_line}-{hi_line}")
+        }
+    }
+
+    /// Returns the real name of the file containing `sp.lo()`, ignoring any name set with
+    /// [`SourceMap::set_display_name`], since callers use it to find files relative to this one.
     pub fn span_to_filename(&self, sp: Span) -> FileName {
         self.lookup_char_pos(sp.lo()).file.name.clone()
     }
 
+    /// Overrides the name shown for `source_file` in diagnostics, e.g. to hide the temporary
+    /// path of a generated file. The file can still be found by its real name with
+    /// [`SourceMap::get_source_file`].
+    pub fn set_display_name(&self, source_file: &SourceFile, name: FileName) {
+        *source_file.display_name.borrow_mut() = Some(name);
+    }
+
     pub fn filename_for_diagnostics<'a>(&self, filename: &'a FileName) -> FileNameDisplay<'a> {
         filename.display(self.path_mapping.filename_display_for_diagnostics)
     }
@@ -535,6 +716,208 @@ pub fn is_valid_span(&self, sp: Span) -> Result<(Loc, Loc), SpanLinesError> {
         Ok((lo, hi))
     }
 
+    /// Truncates `sp` so that it ends no later than the end of the `SourceFile` containing
+    /// `sp.lo()`.
+    ///
+    /// This is a lossy recovery for spans whose endpoints ended up in different files (e.g.
+    /// through macro expansion): everything past the end of the first file is dropped, but the
+    /// resulting span can at least be rendered.
+    pub fn clamp_span_to_file(&self, sp: Span) -> Span {
+        let lo_file = self.lookup_source_file(sp.lo());
+        if lo_file.contains(sp.hi()) { sp } else { sp.with_hi(lo_file.end_position()) }
+    }
+
+    /// Returns the part of `sp` that lies within the (0-based) lines `lines` of its file, including
+    /// their newlines, or `None` if `sp` doesn't cover any byte of those lines.
+    pub fn clip_span_to_lines(&self, sp: Span, lines: Range<usize>) -> Option<Span> {
+        let sf = self.lookup_source_file(sp.lo());
+        let end = cmp::min(lines.end, sf.count_lines());
+        if lines.start >= end {
+            return None;
+        }
+        let lo = cmp::max(sp.lo(), sf.line_bounds(lines.start).start);
+        let hi = cmp::min(sp.hi(), sf.line_bounds(end - 1).end);
+        (lo < hi).then(|| sp.with_lo(lo).with_hi(hi))
+    }
+
+    /// Extends `sp` to cover whole lines, plus up to `up` lines before and `down` lines after it,
+    /// including the newline of the last line. The result is clamped to the `SourceFile`
+    /// containing `sp.lo()`.
+    pub fn grow_span_lines(&self, sp: Span, up: usize, down: usize) -> Span {
+        let sf = self.lookup_source_file(sp.lo());
+        let line_of = |pos: BytePos| sf.lookup_line(sf.relative_position(pos));
+        let (Some(lo_line), Some(hi_line)) =
+            (line_of(sp.lo()), line_of(cmp::min(sp.hi(), sf.end_position())))
+        else {
+            return sp;
+        };
+        let first = lo_line.saturating_sub(up);
+        let last = cmp::min(hi_line.saturating_add(down), sf.count_lines() - 1);
+        sp.with_lo(sf.line_bounds(first).start).with_hi(sf.line_bounds(last).end)
+    }
+
+    /// Splits the range `lo..hi`, which may cross file boundaries, into one span for each
+    /// `SourceFile` it covers at least one byte of. Each span is clamped to the extent of its file.
+    pub fn split_range_by_file(&self, lo: BytePos, hi: BytePos) -> Vec<Span> {
+        let files = self.files.borrow();
+        let first = files.source_files.partition_point(|sf| sf.end_position() <= lo);
+        files
+            .source_files
+            .iter()
+            .skip(first)
+            .take_while(|sf| sf.start_pos < hi)
+            .filter_map(|sf| {
+                let lo = cmp::max(lo, sf.start_pos);
+                let hi = cmp::min(hi, sf.end_position());
+                (lo < hi).then(|| Span::with_root_ctxt(lo, hi))
+            })
+            .collect()
+    }
+
+    /// Returns the source text of `sp`, which may cross file boundaries, by concatenating the
+    /// parts of each `SourceFile` it covers in order. See [`SourceMap::split_range_by_file`].
+    pub fn cross_file_snippet(&self, sp: Span) -> Result<String, SpanSnippetError> {
+        let mut snippet = String::new();
+        for part in self.split_range_by_file(sp.lo(), sp.hi()) {
+            snippet.push_str(&self.span_to_snippet(part)?);
+        }
+        Ok(snippet)
+    }
+
+    /// Returns the smallest span in `spans` that contains `pos`, where a span is considered to
+    /// contain both of its endpoints. If several spans have the same length, the first one is
+    /// returned.
+    pub fn innermost_containing(&self, spans: &[Span], pos: BytePos) -> Option<Span> {
+        spans
+            .iter()
+            .copied()
+            .filter(|sp| sp.lo() <= pos && pos <= sp.hi())
+            .min_by_key(|sp| sp.hi() - sp.lo())
+    }
+
+    /// Merges the spans in `spans` that overlap or touch, returning a sorted list of disjoint
+    /// spans covering the same bytes. All spans are expected to be in the same `SourceFile`.
+    /// A merged span keeps the syntax context and parent of the earliest span it was built from.
+    pub fn coalesce_spans(&self, spans: &[Span]) -> Vec<Span> {
+        let mut sorted = spans.to_vec();
+        sorted.sort_by_key(|sp| (sp.lo(), sp.hi()));
+
+        let mut coalesced: Vec<Span> = Vec::with_capacity(sorted.len());
+        for sp in sorted {
+            match coalesced.last_mut() {
+                Some(last) if sp.lo() <= last.hi() => {
+                    *last = last.with_hi(cmp::max(last.hi(), sp.hi()));
+                }
+                _ => coalesced.push(sp),
+            }
+        }
+        coalesced
+    }
+
+    /// Returns the smallest span that contains all of `spans`, or `None` if `spans` is empty.
+    /// The result has the syntax context and parent of the first span.
+    pub fn enclosing_span(&self, spans: &[Span]) -> Option<Span> {
+        let (first, rest) = spans.split_first()?;
+        let lo = rest.iter().map(|sp| sp.lo()).fold(first.lo(), cmp::min);
+        let hi = rest.iter().map(|sp| sp.hi()).fold(first.hi(), cmp::max);
+        Some(Span::new(lo, hi, first.ctxt(), first.parent()))
+    }
+
+    /// Walks up the macro expansion chain of `sp`, starting with `sp` itself, and returns the
+    /// first span in it that points into `target`, or `None` if the chain never passes through
+    /// `target`. Unlike [`Span::source_callsite`], this can stop at an intermediate expansion.
+    pub fn find_ancestor_in_file(&self, mut sp: Span, target: &SourceFile) -> Option<Span> {
+        loop {
+            if !sp.is_dummy() && self.lookup_source_file(sp.lo()).start_pos == target.start_pos {
+                return Some(sp);
+            }
+            sp = sp.parent_callsite()?;
+        }
+    }
+
+    /// Groups `spans` by the line they start on. Each group has the `SourceFile`, the (0-based)
+    /// line index and the spans starting on that line in their original order. Groups are sorted
+    /// by file and line. Spans in files without any lines are left out.
+    pub fn group_spans_by_line(&self, spans: &[Span]) -> Vec<(Lrc<SourceFile>, usize, Vec<Span>)> {
+        let mut located: Vec<_> = spans
+            .iter()
+            .filter_map(|&sp| {
+                let SourceFileAndLine { sf, line } = self.lookup_line(sp.lo()).ok()?;
+                Some((sf, line, sp))
+            })
+            .collect();
+        located.sort_by_key(|(sf, line, _)| (sf.start_pos, *line));
+
+        let mut groups: Vec<(Lrc<SourceFile>, usize, Vec<Span>)> = Vec::new();
+        for (sf, line, sp) in located {
+            match groups.last_mut() {
+                Some((group_sf, group_line, group))
+                    if group_sf.start_pos == sf.start_pos && *group_line == line =>
+                {
+                    group.push(sp)
+                }
+                _ => groups.push((sf, line, vec![sp])),
+            }
+        }
+        groups
+    }
+
+    /// Returns `true` if `sp` starts at the beginning of a line and ends at the beginning of a
+    /// line or at the end of its file, i.e. it only covers complete lines (including their
+    /// newlines). An empty span at the start of a line counts as covering zero whole lines.
+    /// Spans in empty files have no lines to cover, so this returns `false` for them.
+    pub fn is_whole_line_span(&self, sp: Span) -> bool {
+        let end = self.lookup_source_file(sp.lo()).end_position();
+        let is_line_start = |pos: BytePos| self.line_begin_pos(pos) == Some(pos);
+        is_line_start(sp.lo()) && (sp.hi() == end || is_line_start(sp.hi()))
+    }
+
+    /// Returns `true` if `sp` covers exactly one complete line, from its start up to its end but
+    /// not including its newline. Unlike [`SourceMap::is_whole_line_span`], this rejects spans
+    /// covering several lines.
+    pub fn is_single_full_line(&self, sp: Span) -> bool {
+        let Ok(SourceFileAndLine { sf, line }) = self.lookup_line(sp.lo()) else {
+            return false;
+        };
+        let line_start = sf.absolute_position(sf.lines()[line]);
+        sp.lo() == line_start
+            && sf
+                .get_line(line)
+                .is_some_and(|text| sp.hi() == line_start + BytePos::from_usize(text.len()))
+    }
+
+    /// Returns the `BytePos` of every line start in `sp.lo()..=sp.hi()`. If `sp` starts in the
+    /// middle of a line, the start of that line comes first, so there is one entry for each line
+    /// `sp` touches (plus the following line if `sp` ends right after a newline).
+    pub fn line_starts_in_span(&self, sp: Span) -> Vec<BytePos> {
+        let sf = self.lookup_source_file(sp.lo());
+        let lo = sf.relative_position(sp.lo());
+        let hi = sf.relative_position(cmp::min(sp.hi(), sf.end_position()));
+        let Some(first) = sf.lookup_line(lo) else {
+            return Vec::new();
+        };
+        let lines = sf.lines();
+        let last = lines.partition_point(|&line_start| line_start <= hi);
+        lines[first..last].iter().map(|&line_start| sf.absolute_position(line_start)).collect()
+    }
+
+    /// Returns the `BytePos` of the start of the line containing `lo`, followed by every line
+    /// start in `lo..hi`, e.g. the lines to draw for a viewport showing that range. Unlike
+    /// [`SourceMap::line_starts_in_span`], a line starting exactly at `hi` is not included.
+    ///
+    /// Panics if `lo..hi` is not a range within a single `SourceFile`.
+    pub fn line_starts_in_range(&self, lo: BytePos, hi: BytePos) -> Vec<BytePos> {
+        let sf = self.lookup_source_file(lo);
+        assert!(lo <= hi && sf.contains(hi), "`{lo:?}..{hi:?}` is not a range within one file");
+        let Some(first) = sf.lookup_line(sf.relative_position(lo)) else {
+            return Vec::new();
+        };
+        let hi = sf.relative_position(hi);
+        let lines = sf.lines();
+        let last = cmp::max(first + 1, lines.partition_point(|&line_start| line_start < hi));
+        lines[first..last].iter().map(|&line_start| sf.absolute_position(line_start)).collect()
+    }
+
     pub fn is_line_before_span_empty(&self, sp: Span) -> bool {
         match self.span_to_prev_source(sp) {
             Ok(s) => s.rsplit_once('\n').unwrap_or(("", &s)).1.trim_start().is_empty(),
@@ -577,12 +960,190 @@ pub fn span_to_lines(&self, sp: Span) -> FileLinesResult {
         Ok(FileLines { file: lo.file, lines })
     }
 
+    /// Returns the file of `sp` and the index of every line it touches, each with the
+    /// [`make_caret`] underline of the part covered by `sp`, for the span renderers below. A span
+    /// ending right after a newline covers nothing on the following line, so that line is left
+    /// out. A blank line inside a non-empty span has nothing to underline and gets `None`, while
+    /// an empty span is still marked with a single `^`.
+    fn span_underlines(
+        &self,
+        sp: Span,
+    ) -> Result<(Lrc<SourceFile>, Vec<(usize, Option<String>)>), SpanLinesError> {
+        let FileLines { file, mut lines } = self.span_to_lines(sp)?;
+        if lines.len() > 1 && lines.last().is_some_and(|line| line.start_col == line.end_col) {
+            lines.pop();
+        }
+        let underlines = lines
+            .into_iter()
+            .map(|line| {
+                let len = line.end_col.to_usize().saturating_sub(line.start_col.to_usize());
+                let underline =
+                    (len > 0 || sp.is_empty()).then(|| make_caret(line.start_col.to_usize(), len));
+                (line.line_index, underline)
+            })
+            .collect();
+        Ok((file, underlines))
+    }
+
+    /// Renders every line touched by `sp`, each followed by a [`make_caret`] underline of the part
+    /// of it covered by `sp`. The first and last lines are underlined from and up to the span's
+    /// endpoints, interior lines are underlined in full. An empty span is marked with a single `^`.
+    pub fn render_span_multiline(&self, sp: Span) -> Result<String, SpanLinesError> {
+        let (file, underlines) = self.span_underlines(sp)?;
+        let mut rendered = String::new();
+        for (line_index, underline) in underlines {
+            rendered.push_str(&file.get_line(line_index).unwrap_or_default());
+            rendered.push('\n');
+            rendered.push_str(&underline.unwrap_or_default());
+            rendered.push('\n');
+        }
+        Ok(rendered)
+    }
+
+    /// Renders the lines touched by `sp`, plus up to `before` lines of context before and `after`
+    /// lines after them, each prefixed by its right-aligned 1-based line number and a `|`. Every
+    /// line with a part covered by `sp` is followed by a line with a blank gutter and a
+    /// [`make_caret`] underline of that part. Columns are counted in chars.
+    pub fn render_span_with_gutter(
+        &self,
+        sp: Span,
+        before: usize,
+        after: usize,
+    ) -> Result<String, SpanLinesError> {
+        let (file, underlines) = self.span_underlines(sp)?;
+        let (Some(&(first, _)), Some(&(last, _))) = (underlines.first(), underlines.last()) else {
+            return Ok(String::new());
+        };
+        // There are no lines to render in an empty file.
+        let Some(last_line) = file.count_lines().checked_sub(1) else {
+            return Ok(String::new());
+        };
+        let start = first.saturating_sub(before);
+        let end = cmp::min(last.saturating_add(after), last_line);
+        let width = (end + 1).to_string().len();
+
+        let mut rendered = String::new();
+        for line_index in start..=end {
+            let text = file.get_line(line_index).unwrap_or_default();
+            rendered.push_str(format!("{:>width$} | {text}", line_index + 1).trim_end());
+            rendered.push('\n');
+            let underline = underlines.iter().find(|&&(index, _)| index == line_index);
+            if let Some((_, Some(caret))) = underline {
+                rendered.push_str(&format!("{:width$} | {caret}\n", ""));
+            }
+        }
+        Ok(rendered)
+    }
+
+    /// Returns the display width of the whole (0-based) line `line_index` of `sf`, not counting
+    /// its newline. Tabs and wide characters are expanded the same way as for
+    /// [`Loc::col_display`], so this is the width to right-pad lines to a common column.
+    /// Returns `None` if the line or the source of `sf` is not available.
+    pub fn line_display_width(&self, sf: &SourceFile, line_index: usize) -> Option<usize> {
+        let len = sf.with_line(line_index, str::len)?;
+        let end = sf.absolute_position(sf.lines()[line_index]) + BytePos::from_usize(len);
+        Some(sf.lookup_file_pos_with_col_display(end).2)
+    }
+
+    /// Returns the span of the spaces and tabs at the end of the (0-based) line `line_index` of
+    /// `sf`, not counting its newline. Returns `None` if the line doesn't end in any, or if the
+    /// line or the source of `sf` is not available.
+    pub fn trailing_whitespace_span(&self, sf: &SourceFile, line_index: usize) -> Option<Span> {
+        let (trimmed, len) = sf
+            .with_line(line_index, |text| (text.trim_end_matches([' ', '\t']).len(), text.len()))?;
+        let line_start = sf.absolute_position(sf.lines()[line_index]);
+        (trimmed < len).then(|| {
+            Span::with_root_ctxt(
+                line_start + BytePos::from_usize(trimmed),
+                line_start + BytePos::from_usize(len),
+            )
+        })
+    }
+
+    /// Returns the span of the blank lines at the end of `sf`, i.e. everything after the newline
+    /// that ends the last line with anything other than whitespace on it. Returns `None` if there
+    /// are no trailing blank lines or the source of `sf` is not available.
+    pub fn trailing_blank_lines_span(&self, sf: &SourceFile) -> Option<Span> {
+        let whole = Span::with_root_ctxt(sf.start_pos, sf.end_position());
+        let start = self
+            .span_to_source(whole, |src, _, _| {
+                let content_end = src.trim_end().len();
+                if content_end == 0 {
+                    return Ok(0);
+                }
+                Ok(src[content_end..].find('\n').map_or(src.len(), |nl| content_end + nl + 1))
+            })
+            .ok()?;
+        let lo = sf.start_pos + BytePos::from_usize(start);
+        (lo < sf.end_position()).then(|| whole.with_lo(lo))
+    }
+
+    /// Converts the `CharPos` column `col` of the (0-based) line `line_index` of `sf` into a byte
+    /// offset from the start of that line, stepping over multi-byte characters using the file's
+    /// multi-byte char table. A `col` past the end of the line is clamped to the end of the line,
+    /// not counting its newline. Returns `None` if `sf` has no line `line_index`.
+    pub fn char_col_to_byte_col(
+        &self,
+        sf: &SourceFile,
+        line_index: usize,
+        col: CharPos,
+    ) -> Option<usize> {
+        let lines = sf.lines();
+        let line_start = *lines.get(line_index)?;
+        let line_len = match lines.get(line_index + 1) {
+            Some(&next_line_start) => (next_line_start - line_start).to_usize() - 1,
+            None => (sf.source_len - line_start).to_usize(),
+        };
+        let first = sf.multibyte_chars.partition_point(|mbc| mbc.pos < line_start);
+        let mut byte_col = col.to_usize();
+        for mbc in &sf.multibyte_chars[first..] {
+            if mbc.pos.to_usize() >= line_start.to_usize() + cmp::min(byte_col, line_len) {
+                break;
+            }
+            byte_col += mbc.bytes as usize - 1;
+        }
+        Some(cmp::min(byte_col, line_len))
+    }
+
+    /// Converts `loc` back into the absolute `BytePos` it describes, i.e. the inverse of
+    /// `lookup_char_pos`. A `Loc` in a file without lines (line 0) maps to the start of the file.
+    pub fn loc_to_bytepos(&self, loc: &Loc) -> BytePos {
+        let Some(line_index) = loc.line.checked_sub(1) else {
+            return loc.file.start_pos;
+        };
+        let line_start = loc.file.absolute_position(loc.file.lines()[line_index]);
+        let byte_col = self.char_col_to_byte_col(&loc.file, line_index, loc.col);
+        line_start + BytePos::from_usize(byte_col.expect("`loc.line` is a line of `loc.file`"))
+    }
+
+    /// Returns the sorted, deduplicated 1-based numbers of the lines of `sf` that `offsets` fall
+    /// on. Offsets outside of `sf` are skipped.
+    pub fn offsets_to_lines(&self, sf: &SourceFile, offsets: &[BytePos]) -> Vec<usize> {
+        let mut lines: Vec<usize> = offsets
+            .iter()
+            .filter(|&&pos| sf.contains(pos))
+            .filter_map(|&pos| sf.lookup_line(sf.relative_position(pos)))
+            .map(|line| line + 1)
+            .collect();
+        lines.sort_unstable();
+        lines.dedup();
+        lines
+    }
+
+    /// Returns the index of the closest line before the (0-based) line `line_index` of `sf` that
+    /// contains anything other than whitespace, or `None` if there is no such line.
+    pub fn prev_nonblank_line(&self, sf: &SourceFile, line_index: usize) -> Option<usize> {
+        (0..line_index)
+            .rev()
+            .find(|&idx| sf.get_line(idx).is_some_and(|line| !line.trim().is_empty()))
+    }
+
     /// Extracts the source surrounding the given `Span` using the `extract_source` function. The
     /// extract function takes three arguments: a string slice containing the source, an index in
     /// the slice for the beginning of the span and an index in the slice for the end of the span.
     fn span_to_source<F, T>(&self, sp: Span, extract_source: F) -> Result<T, SpanSnippetError>
     where
-        F: Fn(&str, usize, usize) -> Result<T, SpanSnippetError>,
+        F: FnOnce(&str, usize, usize) -> Result<T, SpanSnippetError>,
     {
         let local_begin = self.lookup_byte_offset(sp.lo());
         let local_end = self.lookup_byte_offset(sp.hi());
@@ -634,6 +1195,186 @@ pub fn span_to_snippet(&self, sp: Span) -> Result<String, SpanSnippetError> {
         })
     }
 
+    /// Returns `true` if `sp` ends at the end of a non-empty `SourceFile` whose last line has no
+    /// trailing newline, which diagnostics can point out with a "no newline at end of file" note.
+    /// Returns `false` if the source of the file is not available.
+    pub fn span_ends_at_eof(&self, sp: Span) -> bool {
+        let sf = self.lookup_source_file(sp.hi());
+        sp.hi() == sf.end_position()
+            && !sf.is_empty()
+            && self
+                .span_to_source(sp.shrink_to_hi(), |src, _, _| Ok(!src.ends_with('\n')))
+                .unwrap_or(false)
+    }
+
+    /// Returns the source snippet corresponding to the given `Span` with each tab replaced by
+    /// enough spaces to reach the next multiple of `tab_width` columns. Columns are counted in
+    /// chars from the start of the line, so the part of the first line before the span is taken
+    /// into account. `tab_width` must not be zero.
+    pub fn span_to_snippet_expanded(
+        &self,
+        sp: Span,
+        tab_width: usize,
+    ) -> Result<String, SpanSnippetError> {
+        self.span_to_source(sp, |src, start_index, end_index| {
+            let snippet =
+                src.get(start_index..end_index).ok_or(SpanSnippetError::IllFormedSpan(sp))?;
+            let line_start = src[..start_index].rfind('\n').map_or(0, |i| i + 1);
+
+            let mut col = 0;
+            let mut expanded = String::with_capacity(snippet.len());
+            for (i, c) in src[line_start..end_index].char_indices() {
+                let in_span = line_start + i >= start_index;
+                match c {
+                    '\t' => {
+                        let width = tab_width - col % tab_width;
+                        if in_span {
+                            expanded.push_str(&" ".repeat(width));
+                        }
+                        col += width;
+                    }
+                    _ => {
+                        if in_span {
+                            expanded.push(c);
+                        }
+                        col = if c == '\n' { 0 } else { col + 1 };
+                    }
+                }
+            }
+            Ok(expanded)
+        })
+    }
+
+    /// Returns the bytes of the source snippet corresponding to the given `Span`. Unlike
+    /// [`SourceMap::span_to_snippet`], this doesn't fail if the span splits a multi-byte
+    /// character.
+    pub fn span_to_bytes(&self, sp: Span) -> Result<Vec<u8>, SpanSnippetError> {
+        self.span_to_source(sp, |src, start_index, end_index| {
+            Ok(src.as_bytes()[start_index..end_index].to_vec())
+        })
+    }
+
+    /// Returns the source snippet between the end of `a` and the start of `b`, e.g. the operator
+    /// between two operands. This is empty if `b` starts before `a` ends, and an error if `a` and
+    /// `b` are in different files.
+    pub fn between_snippet(&self, a: Span, b: Span) -> Result<String, SpanSnippetError> {
+        if !self.same_file(a.hi(), b.lo()) {
+            let begin = self.lookup_source_file(a.hi());
+            let end = self.lookup_source_file(b.lo());
+            return Err(SpanSnippetError::DistinctSources(Box::new(DistinctSources {
+                begin: (begin.name.clone(), begin.start_pos),
+                end: (end.name.clone(), end.start_pos),
+            })));
+        }
+        self.span_to_snippet(Span::with_root_ctxt(a.hi(), cmp::max(a.hi(), b.lo())))
+    }
+
+    /// Returns the source snippet corresponding to the given `Span` split on `\n`, with any `\r`
+    /// before a `\n` removed. A snippet without newlines gives a single line, and a snippet
+    /// ending in a newline gives an empty last line.
+    pub fn span_to_snippet_lines(&self, sp: Span) -> Result<Vec<String>, SpanSnippetError> {
+        let snippet = self.span_to_snippet(sp)?;
+        Ok(snippet
+            .split('\n')
+            .map(|line| line.strip_suffix('\r').unwrap_or(line).to_string())
+            .collect())
+    }
+
+    /// Returns the source snippet corresponding to the given `Span`, keeping only its first `head`
+    /// and last `tail` lines if it is longer than that. The omitted lines are replaced by a single
+    /// `...` line.
+    pub fn span_to_collapsed_snippet(
+        &self,
+        sp: Span,
+        head: usize,
+        tail: usize,
+    ) -> Result<String, SpanSnippetError> {
+        let snippet = self.span_to_snippet(sp)?;
+        let lines: Vec<&str> = snippet.split('\n').collect();
+        if lines.len() <= head + tail {
+            return Ok(snippet);
+        }
+
+        let mut collapsed = lines[..head].to_vec();
+        collapsed.push("...");
+        collapsed.extend_from_slice(&lines[lines.len() - tail..]);
+        Ok(collapsed.join("\n"))
+    }
+
+    /// Calls `f` with each span in `spans` and its source snippet, borrowed instead of copied into
+    /// a `String`, until `f` returns `false`. Spans whose snippet can't be retrieved are skipped.
+    pub fn for_each_span_snippet(&self, spans: &[Span], mut f: impl FnMut(Span, &str) -> bool) {
+        for &sp in spans {
+            let keep_going = self.span_to_source(sp, |src, start_index, end_index| {
+                Ok(src.get(start_index..end_index).map_or(true, |snippet| f(sp, snippet)))
+            });
+            if keep_going == Ok(false) {
+                return;
+            }
+        }
+    }
+
+    /// Returns a hash of the source snippet corresponding to the given `Span`. The hash only
+    /// depends on the snippet's text, not on where it is, and is stable across sessions, so it
+    /// can be persisted to detect whether the source under a span has changed.
+    pub fn span_content_hash(&self, sp: Span) -> Result<Hash64, SpanSnippetError> {
+        self.span_to_source(sp, |src, start_index, end_index| {
+            let snippet =
+                src.get(start_index..end_index).ok_or(SpanSnippetError::IllFormedSpan(sp))?;
+            let mut hasher = StableHasher::new();
+            snippet.hash(&mut hasher);
+            Ok(hasher.finish())
+        })
+    }
+
+    /// Returns the number of occurrences of `c` in the source snippet corresponding to the given
+    /// `Span`, without copying the snippet.
+    pub fn count_char_in_span(&self, sp: Span, c: char) -> Result<usize, SpanSnippetError> {
+        self.span_to_source(sp, |src, start_index, end_index| {
+            let snippet =
+                src.get(start_index..end_index).ok_or(SpanSnippetError::IllFormedSpan(sp))?;
+            Ok(snippet.matches(c).count())
+        })
+    }
+
+    /// Returns `true` if the source snippets of `a` and `b` are the same after collapsing every run
+    /// of whitespace into a single space and dropping leading and trailing whitespace. Returns
+    /// `false` if either snippet is not available.
+    pub fn spans_eq_ignoring_ws(&self, a: Span, b: Span) -> bool {
+        match (self.span_to_snippet(a), self.span_to_snippet(b)) {
+            (Ok(a), Ok(b)) => a.split_whitespace().eq(b.split_whitespace()),
+            _ => false,
+        }
+    }
+
+    /// Returns up to `before` chars of source before `pos` and up to `after` chars after it. The
+    /// result never extends past the `SourceFile` containing `pos`, and never splits a multi-byte
+    /// character.
+    pub fn chars_around(
+        &self,
+        pos: BytePos,
+        before: usize,
+        after: usize,
+    ) -> Result<String, SpanSnippetError> {
+        let sp = Span::with_root_ctxt(pos, pos);
+        self.span_to_source(sp, |src, index, _| {
+            let (head, tail) = (
+                src.get(..index).ok_or(SpanSnippetError::IllFormedSpan(sp))?,
+                src.get(index..).ok_or(SpanSnippetError::IllFormedSpan(sp))?,
+            );
+            let start = head.char_indices().rev().take(before).last().map_or(index, |(i, _)| i);
+            let end = tail.char_indices().nth(after).map_or(src.len(), |(i, _)| index + i);
+            Ok(src[start..end].to_string())
+        })
+    }
+
+    /// Returns the offset in chars of the first char at which the source snippets of `a` and `b`
+    /// differ, or `None` if they are equal or one is a prefix of the other.
+    pub fn first_diff_offset(&self, a: Span, b: Span) -> Result<Option<usize>, SpanSnippetError> {
+        let (a, b) = (self.span_to_snippet(a)?, self.span_to_snippet(b)?);
+        Ok(a.chars().zip(b.chars()).position(|(a, b)| a != b))
+    }
+
     pub fn span_to_margin(&self, sp: Span) -> Option<usize> {
         Some(self.indentation_before(sp)?.len())
     }
@@ -650,6 +1391,160 @@ pub fn indentation_before(&self, sp: Span) -> Option<String> {
         .ok()
     }
 
+    /// Returns the spaces and tabs at the start of the line containing `pos`, or the whole line if
+    /// it consists of nothing else. Unlike [`SourceMap::indentation_before`], this doesn't depend
+    /// on where in the line `pos` is.
+    pub fn line_indent(&self, pos: BytePos) -> Option<String> {
+        self.span_to_snippet(self.line_indent_span(pos)?).ok()
+    }
+
+    /// Returns the width of the indentation of the line containing `pos` in spaces, where a tab
+    /// advances to the next multiple of `tab_width` columns. Returns 0 if the line is not
+    /// available. `tab_width` must not be zero.
+    pub fn indent_width(&self, pos: BytePos, tab_width: usize) -> usize {
+        self.line_indent(pos).unwrap_or_default().chars().fold(0, |width, c| match c {
+            '\t' => width + tab_width - width % tab_width,
+            _ => width + 1,
+        })
+    }
+
+    /// Returns `true` if the indentation of the (0-based) line `line_index` of `sf` contains both
+    /// tabs and spaces.
+    pub fn has_mixed_indent(&self, sf: &SourceFile, line_index: usize) -> bool {
+        let Some(&line_start) = sf.lines().get(line_index) else {
+            return false;
+        };
+        self.line_indent(sf.absolute_position(line_start))
+            .is_some_and(|indent| indent.contains('\t') && indent.contains(' '))
+    }
+
+    /// Returns the span of the spaces and tabs at the start of the line containing `pos`, which
+    /// covers the whole line (without its newline) if it consists of nothing else.
+    pub fn line_indent_span(&self, pos: BytePos) -> Option<Span> {
+        let SourceFileAndLine { sf, line } = self.lookup_line(pos).ok()?;
+        let len =
+            sf.with_line(line, |text| text.len() - text.trim_start_matches([' ', '\t']).len())?;
+        let lo = sf.absolute_position(sf.lines()[line]);
+        Some(Span::with_root_ctxt(lo, lo + BytePos::from_usize(len)))
+    }
+
+    /// Returns the 1-based number, the span and the text of the line containing `pos`, not
+    /// including its newline. Returns `None` if the file has no lines or its source is not
+    /// available.
+    pub fn enclosing_line(&self, pos: BytePos) -> Option<(usize, Span, String)> {
+        let SourceFileAndLine { sf, line } = self.lookup_line(pos).ok()?;
+        let text = sf.get_line(line)?.into_owned();
+        let lo = sf.absolute_position(sf.lines()[line]);
+        let span = Span::with_root_ctxt(lo, lo + BytePos::from_usize(text.len()));
+        Some((line + 1, span, text))
+    }
+
+    /// Returns the line containing `sp.lo()` with the part covered by `sp` bracketed by `»` and
+    /// `«`, for plain-text output. If `sp` spans several lines, the marked part runs to the end of
+    /// the first line. Returns `None` if the line is not available.
+    pub fn line_with_markers(&self, sp: Span) -> Option<String> {
+        let (_, line_span, text) = self.enclosing_line(sp.lo())?;
+        let start = (sp.lo() - line_span.lo()).to_usize();
+        let end = (cmp::min(sp.hi(), line_span.hi()) - line_span.lo()).to_usize();
+        Some(format!("{}»{}«{}", text.get(..start)?, text.get(start..end)?, text.get(end..)?))
+    }
+
+    /// Returns the part of the line containing `sp.lo()` around `sp`, with up to `radius` chars of
+    /// context on either side, and the char column at which `sp` starts within it. If the line
+    /// is cut off on either side, that side is marked with `…`, which the column accounts for.
+    /// This keeps very long lines, e.g. in minified files, readable in diagnostics.
+    pub fn span_focus_window(&self, sp: Span, radius: usize) -> Option<(String, usize)> {
+        let (_, line_span, text) = self.enclosing_line(sp.lo())?;
+        let lo = (sp.lo() - line_span.lo()).to_usize();
+        let hi = (cmp::min(sp.hi(), line_span.hi()) - line_span.lo()).to_usize();
+        let start_char = text.get(..lo)?.chars().count();
+        let end_char = start_char + text.get(lo..hi)?.chars().count();
+        let total = text.chars().count();
+
+        let first = start_char.saturating_sub(radius);
+        let last = cmp::min(end_char + radius, total);
+        let mut window = String::new();
+        if first > 0 {
+            window.push('…');
+        }
+        window.extend(text.chars().skip(first).take(last - first));
+        if last < total {
+            window.push('…');
+        }
+        Some((window, start_char - first + usize::from(first > 0)))
+    }
+
+    /// Returns the span of the block of consecutive non-blank lines containing `pos`, from the
+    /// start of its first line to the end of its last line (not including the newline). A line is
+    /// blank if it contains only whitespace. If `pos` is on a blank line, this is an empty span at
+    /// `pos`.
+    pub fn enclosing_paragraph(&self, pos: BytePos) -> Span {
+        let empty = Span::with_root_ctxt(pos, pos);
+        let Ok(SourceFileAndLine { sf, line }) = self.lookup_line(pos) else {
+            return empty;
+        };
+        let is_blank = |line| sf.get_line(line).map_or(true, |text| text.trim().is_empty());
+        if is_blank(line) {
+            return empty;
+        }
+
+        let first = (0..line).rev().take_while(|&l| !is_blank(l)).last().unwrap_or(line);
+        let last =
+            (line + 1..sf.count_lines()).take_while(|&l| !is_blank(l)).last().unwrap_or(line);
+        let last_len = sf.get_line(last).map_or(0, |text| text.len());
+        let hi = sf.absolute_position(sf.lines()[last]) + BytePos::from_usize(last_len);
+        Span::with_root_ctxt(sf.absolute_position(sf.lines()[first]), hi)
+    }
+
+    /// Returns the span from `pos` to the end of its line, not including the newline. This is
+    /// empty if `pos` is already at the end of the line, or if the source of its file is not
+    /// available.
+    pub fn span_to_line_end(&self, pos: BytePos) -> Span {
+        let end = self.lookup_line(pos).ok().and_then(|SourceFileAndLine { sf, line }| {
+            let line_start = sf.absolute_position(sf.lines()[line]);
+            sf.with_line(line, |text| line_start + BytePos::from_usize(text.len()))
+        });
+        Span::with_root_ctxt(pos, cmp::max(pos, end.unwrap_or(pos)))
+    }
+
+    /// Returns the span from the start of the line containing `pos` up to `pos`. This is empty if
+    /// `pos` is at the start of its line.
+    pub fn span_from_line_start(&self, pos: BytePos) -> Span {
+        let lo = match self.lookup_line(pos) {
+            Ok(SourceFileAndLine { sf, line }) => sf.absolute_position(sf.lines()[line]),
+            Err(_) => pos,
+        };
+        Span::with_root_ctxt(lo, pos)
+    }
+
+    /// Returns `true` if `marker`, e.g. `//`, occurs on the line containing `pos` and ends at or
+    /// before `pos`. This is only a textual heuristic for simple tools: it doesn't know about
+    /// string literals or block comments. Returns `false` if the source is not available.
+    pub fn is_after_line_comment(&self, pos: BytePos, marker: &str) -> bool {
+        self.span_to_snippet(self.span_from_line_start(pos))
+            .is_ok_and(|prefix| prefix.contains(marker))
+    }
+
+    /// Returns the span of the run of non-whitespace chars before `pos` on its line, skipping any
+    /// whitespace directly before `pos`. If only whitespace precedes `pos` on its line, this is an
+    /// empty span at the start of the line.
+    pub fn prev_token_span(&self, pos: BytePos) -> Span {
+        let line = self.span_from_line_start(pos);
+        let Ok(head) = self.span_to_snippet(line) else {
+            return Span::with_root_ctxt(pos, pos);
+        };
+        let trimmed = head.trim_end();
+        let token_start = trimmed
+            .char_indices()
+            .rev()
+            .find(|(_, c)| c.is_whitespace())
+            .map_or(0, |(i, c)| i + c.len_utf8());
+        Span::with_root_ctxt(
+            line.lo() + BytePos::from_usize(token_start),
+            line.lo() + BytePos::from_usize(trimmed.len()),
+        )
+    }
+
     /// Returns the source snippet as `String` before the given `Span`.
     pub fn span_to_prev_source(&self, sp: Span) -> Result<String, SpanSnippetError> {
         self.span_to_source(sp, |src, start_index, _| {
@@ -754,6 +1649,24 @@ pub fn span_extend_to_next_char(&self, sp: Span, c: char, accept_newlines: bool)
         sp
     }
 
+    /// Extends the given `Span` over any whitespace following it and then the next character, if
+    /// that character is one of `chars`. Otherwise, returns the same span. This is useful to
+    /// include the `;` or `,` after an expression when removing it.
+    pub fn span_extend_over_next_char(&self, sp: Span, chars: &[char]) -> Span {
+        self.span_to_source(sp, |src, _, end_index| {
+            let next_source = src.get(end_index..).ok_or(SpanSnippetError::IllFormedSpan(sp))?;
+            let trimmed = next_source.trim_start();
+            Ok(match trimmed.chars().next() {
+                Some(c) if chars.contains(&c) => {
+                    let len = next_source.len() - trimmed.len() + c.len_utf8();
+                    sp.with_hi(sp.hi() + BytePos::from_usize(len))
+                }
+                _ => sp,
+            })
+        })
+        .unwrap_or(sp)
+    }
+
     /// Extends the given `Span` to contain the entire line it is on.
     pub fn span_extend_to_line(&self, sp: Span) -> Span {
         self.span_extend_to_prev_char(self.span_extend_to_next_char(sp, '\n', true), '\n', true)
@@ -871,6 +1784,33 @@ pub fn span_until_whitespace(&self, sp: Span) -> Span {
         self.span_take_while(sp, |c| !c.is_whitespace())
     }
 
+    /// Returns the span of the identifier chars around `pos`, stopping at `:` so that only the
+    /// path segment under the cursor is covered: in `foo::bar`, any position in `bar` gives the
+    /// span of `bar`. If the char at `pos` is a `:`, or the source is not available, this is an
+    /// empty span at `pos`.
+    pub fn path_segment_span_at(&self, pos: BytePos) -> Span {
+        let sp = Span::with_root_ctxt(pos, pos);
+        let is_ident = |c: char| c == '_' || c.is_alphanumeric();
+        let extent = self.span_to_source(sp, |src, index, _| {
+            let (head, tail) = (
+                src.get(..index).ok_or(SpanSnippetError::IllFormedSpan(sp))?,
+                src.get(index..).ok_or(SpanSnippetError::IllFormedSpan(sp))?,
+            );
+            if tail.starts_with(':') {
+                return Ok((0, 0));
+            }
+            let before = head.len() - head.trim_end_matches(is_ident).len();
+            let after = tail.len() - tail.trim_start_matches(is_ident).len();
+            Ok((before, after))
+        });
+        match extent {
+            Ok((before, after)) => sp
+                .with_lo(pos - BytePos::from_usize(before))
+                .with_hi(pos + BytePos::from_usize(after)),
+            Err(_) => sp,
+        }
+    }
+
     /// Given a `Span`, gets a shorter one until `predicate` yields `false`.
     pub fn span_take_while<P>(&self, sp: Span, predicate: P) -> Span
     where
@@ -922,6 +1862,39 @@ pub fn start_point(&self, sp: Span) -> Span {
         sp.with_hi(BytePos(sp.lo().0 + width as u32))
     }
 
+    /// Returns the span of the first character of `sp`, or `sp` itself if it is empty. Unlike
+    /// [`SourceMap::start_point`], the width of the character is taken from the file's
+    /// multi-byte char table, so this also works for files whose source isn't loaded.
+    pub fn span_first_char(&self, sp: Span) -> Span {
+        if sp.is_empty() {
+            return sp;
+        }
+        let sf = self.lookup_source_file(sp.lo());
+        let lo = sf.relative_position(sp.lo());
+        let width = match sf.multibyte_chars.binary_search_by_key(&lo, |mbc| mbc.pos) {
+            Ok(idx) => sf.multibyte_chars[idx].bytes as u32,
+            Err(_) => 1,
+        };
+        sp.with_hi(cmp::min(sp.lo() + BytePos(width), sp.hi()))
+    }
+
+    /// Returns the span of the last character of `sp`, or `sp` itself if it is empty. Like
+    /// [`SourceMap::span_first_char`], this steps back over multi-byte characters using the
+    /// file's multi-byte char table rather than its source.
+    pub fn span_last_char(&self, sp: Span) -> Span {
+        if sp.is_empty() {
+            return sp;
+        }
+        let sf = self.lookup_source_file(sp.lo());
+        let hi = sf.relative_position(sp.hi());
+        let idx = sf.multibyte_chars.partition_point(|mbc| mbc.pos < hi);
+        let width = match idx.checked_sub(1).map(|i| sf.multibyte_chars[i]) {
+            Some(mbc) if mbc.pos.to_u32() + mbc.bytes as u32 == hi.to_u32() => mbc.bytes as u32,
+            _ => 1,
+        };
+        sp.with_lo(cmp::max(sp.hi() - BytePos(width), sp.lo()))
+    }
+
     /// Returns a new span representing just the last character of this span.
     pub fn end_point(&self, sp: Span) -> Span {
         let pos = sp.hi().0;
@@ -1040,12 +2013,56 @@ pub fn get_source_file(&self, filename: &FileName) -> Option<Lrc<SourceFile>> {
         None
     }
 
+    /// Looks up each of `filenames` like [`SourceMap::get_source_file`], returning the results in
+    /// the same order.
+    pub fn get_source_files(&self, filenames: &[FileName]) -> Vec<Option<Lrc<SourceFile>>> {
+        filenames.iter().map(|filename| self.get_source_file(filename)).collect()
+    }
+
+    /// Returns the span of the 1-based line `line` in the file called `filename`, not including
+    /// its newline, e.g. to resolve a `file:line` argument. Returns `None` if there is no such
+    /// file or line, or if the file's source is not available.
+    pub fn named_line_span(&self, filename: &FileName, line: usize) -> Option<Span> {
+        let sf = self.get_source_file(filename)?;
+        let line_start = *sf.lines().get(line.checked_sub(1)?)?;
+        let (_, span, _) = self.enclosing_line(sf.absolute_position(line_start))?;
+        Some(span)
+    }
+
+    /// Returns `true` if the source of the file called `filename` no longer has the hash `since`,
+    /// e.g. a `src_hash` taken from a `SourceFile` of an earlier session. A file that is not in
+    /// this `SourceMap` counts as changed.
+    pub fn file_src_changed(&self, filename: &FileName, since: SourceFileHash) -> bool {
+        !self.get_source_file(filename).is_some_and(|sf| sf.src_hash == since)
+    }
+
+    /// Returns every real `SourceFile` whose path ends with `suffix`. Paths are compared by
+    /// components, so `bar.rs` matches `foo/bar.rs` but not `foobar.rs`. Several files can match a
+    /// short suffix.
+    pub fn find_source_files_by_suffix(&self, suffix: &Path) -> Vec<Lrc<SourceFile>> {
+        self.files()
+            .iter()
+            .filter(|sf| match &sf.name {
+                FileName::Real(name) => name.local_path_if_available().ends_with(suffix),
+                _ => false,
+            })
+            .cloned()
+            .collect()
+    }
+
     /// For a global `BytePos`, computes the local offset within the containing `SourceFile`.
     pub fn lookup_byte_offset(&self, bpos: BytePos) -> SourceFileAndBytePos {
+        let (sf, pos) = self.file_and_offset(bpos);
+        SourceFileAndBytePos { sf, pos }
+    }
+
+    /// Like [`SourceMap::lookup_byte_offset`], but returns the containing `SourceFile` and the
+    /// local offset as a tuple.
+    pub fn file_and_offset(&self, bpos: BytePos) -> (Lrc<SourceFile>, BytePos) {
         let idx = self.lookup_source_file_idx(bpos);
         let sf = (*self.files.borrow().source_files)[idx].clone();
         let offset = bpos - sf.start_pos;
-        SourceFileAndBytePos { sf, pos: offset }
+        (sf, offset)
     }
 
     /// Returns the index of the [`SourceFile`] (in `self.files`) that contains `pos`.
@@ -1055,6 +2072,70 @@ pub fn lookup_source_file_idx(&self, pos: BytePos) -> usize {
         self.files.borrow().source_files.partition_point(|x| x.start_pos <= pos) - 1
     }
 
+    /// Returns a key that orders spans by the index of the `SourceFile` containing `sp.lo()`
+    /// first, and by the file-relative offsets of `sp.lo()` and `sp.hi()` second.
+    ///
+    /// Unlike the absolute positions in a `Span`, these keys stay meaningful when the
+    /// `SourceMap` is rebuilt, as long as the files are registered in the same order.
+    pub fn span_sort_key(&self, sp: Span) -> (usize, usize, usize) {
+        let idx = self.lookup_source_file_idx(sp.lo());
+        let start_pos = (*self.files.borrow().source_files)[idx].start_pos;
+        (idx, (sp.lo() - start_pos).to_usize(), (sp.hi() - start_pos).to_usize())
+    }
+
+    /// Compares `a` and `b` by their [`SourceMap::span_sort_key`], i.e. by file first and by
+    /// position within the file second. Can be passed to `sort_by` directly.
+    pub fn cmp_spans(&self, a: Span, b: Span) -> cmp::Ordering {
+        self.span_sort_key(a).cmp(&self.span_sort_key(b))
+    }
+
+    /// Returns `true` if `pos` does not point into the middle of a multi-byte character of the
+    /// `SourceFile` containing it.
+    pub fn is_char_boundary(&self, pos: BytePos) -> bool {
+        let sf = self.lookup_source_file(pos);
+        let pos = sf.relative_position(pos);
+        let idx = sf.multibyte_chars.partition_point(|mbc| mbc.pos < pos);
+        let Some(mbc) = idx.checked_sub(1).map(|i| sf.multibyte_chars[i]) else { return true };
+        pos.to_u32() >= mbc.pos.to_u32() + mbc.bytes as u32
+    }
+
+    /// Returns `true` if `sp` can be rendered: `sp.lo() <= sp.hi()`, both endpoints lie in the same
+    /// `SourceFile`, and neither points into the middle of a multi-byte character. Unlike
+    /// [`SourceMap::is_valid_span`], this never panics on positions outside of all files, so it
+    /// can be used to check spans that come from deserialization or other external input.
+    pub fn is_well_formed_span(&self, sp: Span) -> bool {
+        let Some(end) = self.files().last().map(|sf| sf.end_position()) else {
+            return false;
+        };
+        sp.lo() <= sp.hi()
+            && sp.hi() <= end
+            && self.lookup_source_file(sp.lo()).contains(sp.hi())
+            && self.is_char_boundary(sp.lo())
+            && self.is_char_boundary(sp.hi())
+    }
+
+    /// Returns the number of multi-byte characters that start before `pos` in the `SourceFile`
+    /// containing it. This is what is needed to convert byte offsets into offsets counted in
+    /// other code units, like those of UTF-16.
+    pub fn multibyte_chars_before(&self, pos: BytePos) -> usize {
+        let sf = self.lookup_source_file(pos);
+        let pos = sf.relative_position(pos);
+        sf.multibyte_chars.partition_point(|mbc| mbc.pos < pos)
+    }
+
+    /// Returns the length in bytes of the character starting at `pos`, taken from the recorded
+    /// multi-byte characters of its `SourceFile`. Any other position is assumed to hold a
+    /// single-byte character.
+    pub fn char_len_at(&self, pos: BytePos) -> usize {
+        let sf = self.lookup_source_file(pos);
+        let pos = sf.relative_position(pos);
+        match sf.multibyte_chars.binary_search_by_key(&pos, |mbc| mbc.pos) {
+            Ok(i) => sf.multibyte_chars[i].bytes as usize,
+            Err(_) => 1,
+        }
+    }
+
+    /// Returns the total number of lines in all `SourceFile`s.
     pub fn count_lines(&self) -> usize {
         self.files().iter().fold(0, |a, f| a + f.count_lines())
     }
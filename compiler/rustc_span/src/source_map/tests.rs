@@ -1,16 +1,23 @@
 use super::*;
 
 use rustc_data_structures::sync::{FreezeLock, Lrc};
+use rustc_serialize::opaque::{FileEncoder, MemDecoder};
 
 fn init_source_map() -> SourceMap {
     let sm = SourceMap::new(FilePathMapping::empty());
-    sm.new_source_file(PathBuf::from("blork.rs").into(), "first line.\nsecond line".to_string());
-    sm.new_source_file(PathBuf::from("empty.rs").into(), String::new());
-    sm.new_source_file(PathBuf::from("blork2.rs").into(), "first line.\nsecond line".to_string());
+    sm.add_test_file("blork.rs", "first line.\nsecond line");
+    sm.add_test_file("empty.rs", "");
+    sm.add_test_file("blork2.rs", "first line.\nsecond line");
     sm
 }
 
 impl SourceMap {
+    /// Registers a new `SourceFile` called `name` containing `src`. Line starts and multi-byte
+    /// chars are recorded as usual, so spans into the file can be built right away.
+    fn add_test_file(&self, name: &str, src: &str) -> Lrc<SourceFile> {
+        self.new_source_file(PathBuf::from(name).into(), src.to_string())
+    }
+
     /// Returns `Some(span)`, a union of the LHS and RHS span. The LHS must precede the RHS. If
     /// there are gaps between LHS and RHS, the resulting union will cross these gaps.
     /// For this to work,
@@ -104,14 +111,8 @@ fn t5() {
 fn init_source_map_mbc() -> SourceMap {
     let sm = SourceMap::new(FilePathMapping::empty());
     // "€" is a three-byte UTF8 char.
-    sm.new_source_file(
-        PathBuf::from("blork.rs").into(),
-        "fir€st €€€€ line.\nsecond line".to_string(),
-    );
-    sm.new_source_file(
-        PathBuf::from("blork2.rs").into(),
-        "first line€€.\n€ second line".to_string(),
-    );
+    sm.add_test_file("blork.rs", "fir€st €€€€ line.\nsecond line");
+    sm.add_test_file("blork2.rs", "first line€€.\n€ second line");
     sm
 }
 
@@ -594,3 +595,1618 @@ fn read_binary_file_handles_lying_stat() {
     let bin = RealFileLoader.read_binary_file(kernel_max).unwrap();
     assert_eq!(&real[..], &bin[..]);
 }
+
+/// Tests `clamp_span_to_file` for a span whose endpoints are in different files.
+#[test]
+fn clamp_span_to_file() {
+    let sm = init_source_map();
+    let blork = sm.lookup_source_file(BytePos(0));
+
+    // A span within a single file is left alone.
+    let span = Span::with_root_ctxt(BytePos(12), BytePos(23));
+    assert_eq!(sm.clamp_span_to_file(span), span);
+
+    // A span running from `blork.rs` into `blork2.rs` is cut at the end of `blork.rs`.
+    let span = Span::with_root_ctxt(BytePos(12), BytePos(30));
+    let clamped = sm.clamp_span_to_file(span);
+    assert_eq!(clamped.lo(), BytePos(12));
+    assert_eq!(clamped.hi(), blork.end_position());
+    assert_eq!(sm.span_to_snippet(clamped), Ok("second line".to_string()));
+}
+
+/// Tests `innermost_containing` with nested spans.
+#[test]
+fn innermost_containing() {
+    let sm = SourceMap::new(FilePathMapping::empty());
+    let inputtext = "foo(bar(baz), qux)\n";
+    let selection1 = "~~~~~~~~~~~~~~~~~~\n";
+    let selection2 = "    ~~~~~~~~      \n";
+    let selection3 = "        ~~~       \n";
+    let selection4 = "              ~~~ \n";
+    sm.new_source_file(Path::new("blork.rs").to_owned().into(), inputtext.to_owned());
+    let call = span_from_selection(inputtext, selection1);
+    let inner_call = span_from_selection(inputtext, selection2);
+    let baz = span_from_selection(inputtext, selection3);
+    let qux = span_from_selection(inputtext, selection4);
+    let spans = [call, inner_call, baz, qux];
+
+    assert_eq!(sm.innermost_containing(&spans, BytePos(9)), Some(baz));
+    assert_eq!(sm.innermost_containing(&spans, BytePos(5)), Some(inner_call));
+    assert_eq!(sm.innermost_containing(&spans, BytePos(15)), Some(qux));
+    assert_eq!(sm.innermost_containing(&spans, BytePos(1)), Some(call));
+    assert_eq!(sm.innermost_containing(&spans, BytePos(19)), None);
+    assert_eq!(sm.innermost_containing(&[], BytePos(1)), None);
+}
+
+/// Tests `render_span_multiline` for a span covering the end of one line, a whole line, and the
+/// start of another, a span ending right after a newline, and an empty span.
+#[test]
+fn render_span_multiline() {
+    let sm = SourceMap::new(FilePathMapping::empty());
+    let inputtext = "aaaaa\nbbbbBB\nCCC\nDDDDDddddd\neee\n";
+    let selection = "     \n    ~~\n~~~\n~~~~~     \n   \n";
+    sm.new_source_file(Path::new("blork.rs").to_owned().into(), inputtext.to_string());
+    let span = span_from_selection(inputtext, selection);
+
+    let rendered = sm.render_span_multiline(span).unwrap();
+    assert_eq!(rendered, "bbbbBB\n    ^~\nCCC\n^~~\nDDDDDddddd\n^~~~~\n");
+
+    let span = Span::with_root_ctxt(BytePos(0), BytePos(6));
+    assert_eq!(sm.render_span_multiline(span).unwrap(), "aaaaa\n^~~~~\n");
+    let span = Span::with_root_ctxt(BytePos(2), BytePos(2));
+    assert_eq!(sm.render_span_multiline(span).unwrap(), "aaaaa\n  ^\n");
+    // The blank line inside this span has nothing to underline.
+    let file = sm.add_test_file("blank.rs", "fn f() {\n\n}\n");
+    let span = Span::with_root_ctxt(file.start_pos, file.start_pos + BytePos(11));
+    assert_eq!(sm.render_span_multiline(span).unwrap(), "fn f() {\n^~~~~~~~\n\n\n}\n^\n");
+}
+
+/// Tests that `span_sort_key` orders spans by file before offset.
+#[test]
+fn span_sort_key() {
+    let sm = init_source_map();
+    let blork_span = Span::with_root_ctxt(BytePos(12), BytePos(23));
+    let blork2_span = Span::with_root_ctxt(BytePos(25), BytePos(30));
+
+    assert_eq!(sm.span_sort_key(blork_span), (0, 12, 23));
+    assert_eq!(sm.span_sort_key(blork2_span), (2, 0, 5));
+
+    let mut spans = vec![blork2_span, blork_span];
+    spans.sort_by_key(|&sp| sm.span_sort_key(sp));
+    assert_eq!(spans, [blork_span, blork2_span]);
+}
+
+/// Tests that diagnostics use the name set with `set_display_name`, while lookups by name still
+/// use the real name.
+#[test]
+fn set_display_name() {
+    let sm = init_source_map();
+    let blork = sm.get_source_file(&PathBuf::from("blork.rs").into()).unwrap();
+    sm.set_display_name(&blork, PathBuf::from("generated.rs").into());
+
+    let span = Span::with_root_ctxt(BytePos(12), BytePos(23));
+    assert_eq!(sm.span_to_diagnostic_string(span), "generated.rs:2:1: 2:12");
+    assert_eq!(sm.span_to_filename(span), PathBuf::from("blork.rs").into());
+
+    let found = sm.get_source_file(&PathBuf::from("blork.rs").into()).unwrap();
+    assert_eq!(found.start_pos, blork.start_pos);
+    assert_eq!(found.name, PathBuf::from("blork.rs").into());
+    assert!(sm.get_source_file(&PathBuf::from("generated.rs").into()).is_none());
+}
+
+/// Tests `is_char_boundary` around a multi-byte char.
+#[test]
+fn is_char_boundary() {
+    let sm = init_source_map_mbc();
+
+    // "€" occupies bytes 3 to 5 of "fir€st".
+    assert!(sm.is_char_boundary(BytePos(0)));
+    assert!(sm.is_char_boundary(BytePos(3)));
+    assert!(!sm.is_char_boundary(BytePos(4)));
+    assert!(!sm.is_char_boundary(BytePos(5)));
+    assert!(sm.is_char_boundary(BytePos(6)));
+}
+
+/// Tests that files registered with `add_test_file` resolve positions correctly.
+#[test]
+fn add_test_file() {
+    let sm = init_source_map();
+    let file = sm.add_test_file("added.rs", "ab\n€c");
+    assert_eq!(file.start_pos, BytePos(49));
+    assert_eq!(file.lines(), &[RelativeBytePos(0), RelativeBytePos(3)]);
+    assert_eq!(file.multibyte_chars, [MultiByteChar { pos: RelativeBytePos(3), bytes: 3 }]);
+
+    let loc = sm.lookup_char_pos(file.start_pos + BytePos(6));
+    assert_eq!(loc.file.name, PathBuf::from("added.rs").into());
+    assert_eq!(loc.line, 2);
+    assert_eq!(loc.col, CharPos(1));
+}
+
+/// Tests `split_range_by_file` for a range covering parts of three files.
+#[test]
+fn split_range_by_file() {
+    let sm = SourceMap::new(FilePathMapping::empty());
+    sm.add_test_file("a.rs", "abc");
+    sm.add_test_file("b.rs", "defg");
+    sm.add_test_file("c.rs", "hi");
+
+    let spans = sm.split_range_by_file(BytePos(1), BytePos(10));
+    assert_eq!(
+        spans,
+        [
+            Span::with_root_ctxt(BytePos(1), BytePos(3)),
+            Span::with_root_ctxt(BytePos(4), BytePos(8)),
+            Span::with_root_ctxt(BytePos(9), BytePos(10)),
+        ]
+    );
+    let snippets: Vec<_> = spans.iter().map(|&sp| sm.span_to_snippet(sp).unwrap()).collect();
+    assert_eq!(snippets, ["bc", "defg", "h"]);
+
+    // A range within a single file is returned as is.
+    assert_eq!(
+        sm.split_range_by_file(BytePos(5), BytePos(7)),
+        [Span::with_root_ctxt(BytePos(5), BytePos(7))]
+    );
+}
+
+/// Tests `cross_file_snippet` for spans within one file and across adjacent files.
+#[test]
+fn cross_file_snippet() {
+    let sm = SourceMap::new(FilePathMapping::empty());
+    sm.add_test_file("a.rs", "abc");
+    sm.add_test_file("b.rs", "defg");
+    sm.add_test_file("c.rs", "hi");
+    let span = |lo, hi| Span::with_root_ctxt(BytePos(lo), BytePos(hi));
+
+    assert_eq!(sm.cross_file_snippet(span(1, 6)), Ok("bcde".to_string()));
+    assert_eq!(sm.cross_file_snippet(span(0, 11)), Ok("abcdefghi".to_string()));
+    assert_eq!(sm.cross_file_snippet(span(5, 7)), Ok("ef".to_string()));
+    assert_eq!(sm.cross_file_snippet(span(2, 2)), Ok(String::new()));
+}
+
+/// Tests `line_display_width` with tabs and wide chars.
+#[test]
+fn line_display_width() {
+    let sm = SourceMap::new(FilePathMapping::empty());
+    let file = sm.add_test_file("blork.rs", "\tab\n日本\nplain\n");
+
+    assert_eq!(sm.line_display_width(&file, 0), Some(6));
+    assert_eq!(sm.line_display_width(&file, 1), Some(4));
+    assert_eq!(sm.line_display_width(&file, 2), Some(5));
+    assert_eq!(sm.line_display_width(&file, 3), None);
+}
+
+/// Tests that `span_to_lines` reports an error instead of panicking for malformed spans.
+#[test]
+fn span_to_lines_malformed() {
+    let sm = init_source_map();
+
+    // `Span::new` swaps backwards endpoints, so such a span is as good as the forward one.
+    let backwards = Span::with_root_ctxt(BytePos(23), BytePos(12));
+    let FileLines { file, lines } = sm.span_to_lines(backwards).unwrap();
+    assert_eq!(file.name, PathBuf::from("blork.rs").into());
+    assert_eq!(lines, [LineInfo { line_index: 1, start_col: CharPos(0), end_col: CharPos(11) }]);
+
+    let cross_file = Span::with_root_ctxt(BytePos(12), BytePos(30));
+    assert_eq!(
+        sm.span_to_lines(cross_file).err(),
+        Some(SpanLinesError::DistinctSources(Box::new(DistinctSources {
+            begin: (PathBuf::from("blork.rs").into(), BytePos(0)),
+            end: (PathBuf::from("blork2.rs").into(), BytePos(25)),
+        })))
+    );
+}
+
+/// Tests that `file_ranges` returns ordered, non-overlapping ranges.
+#[test]
+fn file_ranges() {
+    let sm = init_source_map();
+    let ranges = sm.file_ranges();
+
+    let names: Vec<_> = ranges.iter().map(|(sf, _)| sf.name.clone()).collect();
+    assert_eq!(
+        names,
+        [
+            PathBuf::from("blork.rs").into(),
+            PathBuf::from("empty.rs").into(),
+            PathBuf::from("blork2.rs").into(),
+        ]
+    );
+    let ranges: Vec<_> = ranges.into_iter().map(|(_, range)| range).collect();
+    assert_eq!(
+        ranges,
+        [BytePos(0)..BytePos(23), BytePos(24)..BytePos(24), BytePos(25)..BytePos(48)]
+    );
+    for pair in ranges.windows(2) {
+        assert!(pair[0].end < pair[1].start);
+    }
+}
+
+/// Tests `lookup_cols` on lines with and without multi-byte chars.
+#[test]
+fn lookup_cols() {
+    let sm = init_source_map_mbc();
+
+    // "fir€s" is 7 bytes but only 5 chars.
+    assert_eq!(sm.lookup_cols(BytePos(7)), (7, CharPos(5)));
+    assert_eq!(sm.lookup_cols(BytePos(3)), (3, CharPos(3)));
+
+    // The second line, "second line", has no multi-byte chars.
+    assert_eq!(sm.lookup_cols(BytePos(31)), (3, CharPos(3)));
+}
+
+/// Tests `span_first_char` for spans starting with ASCII and multi-byte chars.
+#[test]
+fn span_first_char() {
+    let sm = init_source_map_mbc();
+
+    let ascii = Span::with_root_ctxt(BytePos(0), BytePos(8));
+    assert_eq!(sm.span_first_char(ascii), Span::with_root_ctxt(BytePos(0), BytePos(1)));
+
+    let multibyte = Span::with_root_ctxt(BytePos(3), BytePos(8));
+    let first = sm.span_first_char(multibyte);
+    assert_eq!(first, Span::with_root_ctxt(BytePos(3), BytePos(6)));
+    assert_eq!(sm.span_to_snippet(first), Ok("€".to_string()));
+
+    let empty = Span::with_root_ctxt(BytePos(3), BytePos(3));
+    assert_eq!(sm.span_first_char(empty), empty);
+}
+
+/// Tests `span_last_char` for spans ending with ASCII and multi-byte chars.
+#[test]
+fn span_last_char() {
+    let sm = init_source_map_mbc();
+
+    let ascii = Span::with_root_ctxt(BytePos(0), BytePos(8));
+    assert_eq!(sm.span_last_char(ascii), Span::with_root_ctxt(BytePos(7), BytePos(8)));
+
+    let multibyte = Span::with_root_ctxt(BytePos(0), BytePos(6));
+    let last = sm.span_last_char(multibyte);
+    assert_eq!(last, Span::with_root_ctxt(BytePos(3), BytePos(6)));
+    assert_eq!(sm.span_to_snippet(last), Ok("€".to_string()));
+
+    let empty = Span::with_root_ctxt(BytePos(6), BytePos(6));
+    assert_eq!(sm.span_last_char(empty), empty);
+}
+
+/// Tests `prev_nonblank_line` with leading and interior blank lines.
+#[test]
+fn prev_nonblank_line() {
+    let sm = SourceMap::new(FilePathMapping::empty());
+    let file = sm.add_test_file("blork.rs", "\n  \nfn f() {\n\n\t\n    x\n}\n");
+
+    assert_eq!(sm.prev_nonblank_line(&file, 5), Some(2));
+    assert_eq!(sm.prev_nonblank_line(&file, 6), Some(5));
+    assert_eq!(sm.prev_nonblank_line(&file, 2), None);
+    assert_eq!(sm.prev_nonblank_line(&file, 0), None);
+}
+
+/// Tests that files with synthetic names, like input read from stdin, are not treated as files
+/// on disk.
+#[test]
+fn synthetic_file_is_not_real() {
+    let sm = SourceMap::new(FilePathMapping::empty());
+    let real = sm.add_test_file("blork.rs", "fn main() {}");
+    let stdin =
+        sm.new_source_file(FileName::Custom("<stdin>".to_string()), "fn f() {}".to_string());
+    let anon = sm.new_source_file(FileName::anon_source_code("x"), "x".to_string());
+
+    assert!(real.is_real_file());
+    assert!(!stdin.is_real_file());
+    assert!(!anon.is_real_file());
+}
+
+/// Tests that `lookup_full` agrees with `lookup_char_pos` and records the looked up position.
+#[test]
+fn lookup_full() {
+    let sm = init_source_map_mbc();
+    let pos = BytePos(36);
+
+    let full = sm.lookup_full(pos);
+    let loc = sm.lookup_char_pos(pos);
+    assert_eq!(full.file.start_pos, loc.file.start_pos);
+    assert_eq!((full.line, full.col), (loc.line, loc.col));
+    assert_eq!(full.abs_pos, pos);
+    assert_eq!(full.file_offset.to_u32(), (full.abs_pos - full.file.start_pos).to_u32());
+    assert_eq!(full.file.absolute_position(full.file_offset), pos);
+}
+
+/// Tests `enclosing_span` over overlapping and nested spans.
+#[test]
+fn enclosing_span() {
+    let sm = SourceMap::new(FilePathMapping::empty());
+    let inputtext = "foo(bar, baz(qux), quux)\n";
+    let selection1 = "    ~~~                 \n";
+    let selection2 = "         ~~~~~~~~       \n";
+    let selection3 = "             ~~~        \n";
+    let selection4 = "                   ~~~~ \n";
+    sm.new_source_file(Path::new("blork.rs").to_owned().into(), inputtext.to_owned());
+    let bar = span_from_selection(inputtext, selection1);
+    let baz_call = span_from_selection(inputtext, selection2);
+    let qux = span_from_selection(inputtext, selection3);
+    let quux = span_from_selection(inputtext, selection4);
+
+    let args = sm.enclosing_span(&[baz_call, quux, qux, bar]).unwrap();
+    assert_eq!(sm.span_to_snippet(args), Ok("bar, baz(qux), quux".to_string()));
+    assert_eq!(sm.enclosing_span(&[baz_call, qux]), Some(baz_call));
+    assert_eq!(sm.enclosing_span(&[qux]), Some(qux));
+    assert_eq!(sm.enclosing_span(&[]), None);
+}
+
+/// Tests that the file index survives a round trip through `encode_file_index` and
+/// `decode_file_index`.
+#[test]
+fn file_index_round_trip() {
+    let sm = init_source_map();
+    let index = sm.file_index();
+    assert_eq!(
+        index,
+        [
+            SourceFileIndexEntry {
+                name: PathBuf::from("blork.rs").into(),
+                start_pos: BytePos(0),
+                source_len: RelativeBytePos(23),
+            },
+            SourceFileIndexEntry {
+                name: PathBuf::from("empty.rs").into(),
+                start_pos: BytePos(24),
+                source_len: RelativeBytePos(0),
+            },
+            SourceFileIndexEntry {
+                name: PathBuf::from("blork2.rs").into(),
+                start_pos: BytePos(25),
+                source_len: RelativeBytePos(23),
+            },
+        ]
+    );
+
+    let tmpfile = tempfile::NamedTempFile::new().unwrap();
+    let mut encoder = FileEncoder::new(tmpfile.path()).unwrap();
+    sm.encode_file_index(&mut encoder);
+    encoder.finish().unwrap();
+
+    let data = fs::read(tmpfile.path()).unwrap();
+    let mut decoder = MemDecoder::new(&data, 0);
+    assert_eq!(SourceMap::decode_file_index(&mut decoder), index);
+}
+
+/// Tests `find_source_files_by_suffix` for unique, ambiguous and partial-component suffixes.
+#[test]
+fn find_source_files_by_suffix() {
+    let sm = SourceMap::new(FilePathMapping::empty());
+    sm.add_test_file("src/foo/bar.rs", "");
+    sm.add_test_file("tests/foo/bar.rs", "");
+    sm.add_test_file("src/baz.rs", "");
+    sm.add_test_file("src/foobaz.rs", "");
+    let names = |suffix: &str| -> Vec<FileName> {
+        sm.find_source_files_by_suffix(Path::new(suffix)).iter().map(|sf| sf.name.clone()).collect()
+    };
+
+    assert_eq!(names("src/baz.rs"), [PathBuf::from("src/baz.rs").into()]);
+    assert_eq!(
+        names("foo/bar.rs"),
+        [PathBuf::from("src/foo/bar.rs").into(), PathBuf::from("tests/foo/bar.rs").into()]
+    );
+    assert_eq!(names("baz.rs"), [PathBuf::from("src/baz.rs").into()]);
+    assert!(names("oo/bar.rs").is_empty());
+}
+
+/// A `HashStableContext` that is just good enough to create expansions in tests.
+struct TestHashCtx;
+
+impl HashStableContext for TestHashCtx {
+    fn def_path_hash(&self, _: DefId) -> DefPathHash {
+        DefPathHash::new(def_id::StableCrateId(Hash64::ZERO), Hash64::ZERO)
+    }
+
+    fn hash_spans(&self) -> bool {
+        false
+    }
+
+    fn unstable_opts_incremental_ignore_spans(&self) -> bool {
+        true
+    }
+
+    fn def_span(&self, _: LocalDefId) -> Span {
+        DUMMY_SP
+    }
+
+    fn span_data_to_lines_and_cols(
+        &mut self,
+        _: &SpanData,
+    ) -> Option<(Lrc<SourceFile>, usize, BytePos, usize, BytePos)> {
+        None
+    }
+
+    fn hashing_controls(&self) -> HashingControls {
+        HashingControls { hash_spans: false }
+    }
+}
+
+/// Marks `sp` as coming from a fresh expansion of the bang macro `name`, called at `call_site`.
+/// Must be called with session globals set.
+fn expand_at(sp: Span, call_site: Span, name: &str) -> Span {
+    let kind = ExpnKind::Macro(MacroKind::Bang, Symbol::intern(name));
+    let expn_data = ExpnData::default(kind, call_site, Edition::Edition2021, None, None);
+    sp.fresh_expansion(LocalExpnId::fresh(expn_data, TestHashCtx))
+}
+
+/// Tests `find_ancestor_in_file` for a macro expanding to a call of another macro.
+#[test]
+fn find_ancestor_in_file() {
+    create_default_session_globals_then(|| {
+        let sm = SourceMap::new(FilePathMapping::empty());
+        let user = sm.add_test_file("user.rs", "outer!();");
+        let outer = sm.add_test_file("outer.rs", "inner!()");
+        let inner = sm.add_test_file("inner.rs", "1 + 1");
+        let other = sm.add_test_file("other.rs", "");
+
+        let outer_call = Span::with_root_ctxt(user.start_pos, user.start_pos + BytePos(8));
+        let inner_call = expand_at(
+            Span::with_root_ctxt(outer.start_pos, outer.end_position()),
+            outer_call,
+            "outer",
+        );
+        let expr = expand_at(
+            Span::with_root_ctxt(inner.start_pos, inner.end_position()),
+            inner_call,
+            "inner",
+        );
+
+        assert_eq!(sm.find_ancestor_in_file(expr, &inner), Some(expr));
+        assert_eq!(sm.find_ancestor_in_file(expr, &outer), Some(inner_call));
+        assert_eq!(sm.find_ancestor_in_file(expr, &user), Some(outer_call));
+        assert_eq!(sm.find_ancestor_in_file(expr, &other), None);
+    })
+}
+
+/// Tests `multibyte_chars_before` at positions around several multi-byte chars.
+#[test]
+fn multibyte_chars_before() {
+    let sm = init_source_map_mbc();
+
+    // "fir€st €€€€ line." has multi-byte chars at bytes 3, 9, 12, 15 and 18.
+    assert_eq!(sm.multibyte_chars_before(BytePos(0)), 0);
+    assert_eq!(sm.multibyte_chars_before(BytePos(3)), 0);
+    assert_eq!(sm.multibyte_chars_before(BytePos(6)), 1);
+    assert_eq!(sm.multibyte_chars_before(BytePos(15)), 3);
+    assert_eq!(sm.multibyte_chars_before(BytePos(21)), 5);
+    assert_eq!(sm.multibyte_chars_before(BytePos(30)), 5);
+
+    // Chars in other files are not counted.
+    assert_eq!(sm.multibyte_chars_before(BytePos(40)), 0);
+}
+
+/// Tests that positions in a zero-length `SourceFile` can be resolved without panicking, even
+/// though such a file has no lines.
+#[test]
+fn empty_file_lookups() {
+    let sm = SourceMap::new(FilePathMapping::empty());
+    sm.add_test_file("before.rs", "x");
+    let empty = sm.add_test_file("empty.rs", "");
+    sm.add_test_file("after.rs", "y");
+    assert_eq!(empty.count_lines(), 0);
+
+    let loc = sm.lookup_char_pos(empty.start_pos);
+    assert_eq!(loc.file.name, PathBuf::from("empty.rs").into());
+    assert_eq!((loc.line, loc.col, loc.col_display), (0, CharPos(0), 0));
+    assert!(sm.lookup_line(empty.start_pos).is_err());
+    assert_eq!(empty.get_line(0), None);
+    assert_eq!(empty.line_bounds(0), empty.start_pos..empty.start_pos);
+
+    let span = Span::with_root_ctxt(empty.start_pos, empty.start_pos);
+    assert_eq!(sm.span_to_snippet(span), Ok(String::new()));
+    assert!(!sm.is_multiline(span));
+}
+
+/// Tests `line_indent` for spaces, tabs, mixed indentation, and blank lines.
+#[test]
+fn line_indent() {
+    let sm = SourceMap::new(FilePathMapping::empty());
+    let file = sm.add_test_file("blork.rs", "    spaces\n\t\ttabs\n \t mixed\n\n \t\nnone");
+    let line_start = |line: usize| file.absolute_position(file.lines()[line]);
+
+    assert_eq!(sm.line_indent(line_start(0)).as_deref(), Some("    "));
+    assert_eq!(sm.line_indent(line_start(0) + BytePos(6)).as_deref(), Some("    "));
+    assert_eq!(sm.line_indent(line_start(1)).as_deref(), Some("\t\t"));
+    assert_eq!(sm.line_indent(line_start(2) + BytePos(4)).as_deref(), Some(" \t "));
+    assert_eq!(sm.line_indent(line_start(3)).as_deref(), Some(""));
+    assert_eq!(sm.line_indent(line_start(4)).as_deref(), Some(" \t"));
+    assert_eq!(sm.line_indent(line_start(5)).as_deref(), Some(""));
+}
+
+/// Tests that spans with the same positions but from different expansions compare unequal,
+/// while `source_equal` only looks at the positions.
+#[test]
+fn span_eq_includes_expansion() {
+    create_default_session_globals_then(|| {
+        let sm = SourceMap::new(FilePathMapping::empty());
+        let file = sm.add_test_file("blork.rs", "a!(); b!(); 1 + 1");
+        let pos = |lo: u32, hi: u32| {
+            Span::with_root_ctxt(file.start_pos + BytePos(lo), file.start_pos + BytePos(hi))
+        };
+        let body = pos(12, 17);
+
+        let from_a = expand_at(body, pos(0, 4), "a");
+        let from_b = expand_at(body, pos(6, 10), "b");
+        let from_a_again = expand_at(body, pos(0, 4), "a");
+
+        assert!(from_a.source_equal(from_b));
+        assert_ne!(from_a, from_b);
+        assert!(!from_a.eq_ctxt(from_b));
+        assert_ne!(from_a, from_a_again);
+        assert_ne!(from_a, body);
+        assert_eq!(from_a, from_a.with_lo(from_a.lo()));
+    })
+}
+
+/// Tests `char_col_to_byte_col` on lines with and without multi-byte chars, past the end of a
+/// line and on a line that doesn't exist.
+#[test]
+fn char_col_to_byte_col() {
+    let sm = init_source_map_mbc();
+    let blork2 = sm.lookup_source_file(BytePos(40));
+
+    // The second line of blork2.rs is "€ second line".
+    assert_eq!(sm.char_col_to_byte_col(&blork2, 1, CharPos(0)), Some(0));
+    assert_eq!(sm.char_col_to_byte_col(&blork2, 1, CharPos(1)), Some(3));
+    assert_eq!(sm.char_col_to_byte_col(&blork2, 1, CharPos(5)), Some(7));
+    assert_eq!(sm.char_col_to_byte_col(&blork2, 1, CharPos(40)), Some(15));
+
+    // The first line is "first line€€.", whose multi-byte chars must not affect the second line.
+    assert_eq!(sm.char_col_to_byte_col(&blork2, 0, CharPos(10)), Some(10));
+    assert_eq!(sm.char_col_to_byte_col(&blork2, 0, CharPos(12)), Some(16));
+    // Nor must the second line's multi-byte char affect a column past the end of the first.
+    assert_eq!(sm.char_col_to_byte_col(&blork2, 0, CharPos(14)), Some(17));
+
+    assert_eq!(sm.char_col_to_byte_col(&blork2, 2, CharPos(0)), None);
+}
+
+/// Tests `group_spans_by_line` for spans on two lines, given out of order.
+#[test]
+fn group_spans_by_line() {
+    let sm = SourceMap::new(FilePathMapping::empty());
+    let inputtext = "let a = b;\nf(a, c);\n";
+    let selection1 = "    ~     \n        \n";
+    let selection2 = "          \n  ~     \n";
+    let selection3 = "          \n     ~  \n";
+    sm.new_source_file(Path::new("blork.rs").to_owned().into(), inputtext.to_owned());
+    let a = span_from_selection(inputtext, selection1);
+    let a_arg = span_from_selection(inputtext, selection2);
+    let c_arg = span_from_selection(inputtext, selection3);
+
+    let groups = sm.group_spans_by_line(&[c_arg, a, a_arg]);
+    let groups: Vec<_> = groups.into_iter().map(|(_, line, spans)| (line, spans)).collect();
+    assert_eq!(groups, [(0, vec![a]), (1, vec![c_arg, a_arg])]);
+    assert!(sm.group_spans_by_line(&[]).is_empty());
+}
+
+/// Tests `span_extend_over_next_char` with and without whitespace before the character.
+#[test]
+fn span_extend_over_next_char() {
+    let sm = SourceMap::new(FilePathMapping::empty());
+    let inputtext = "foo(bar) ;\nf(x  , y)\n";
+    let selection1 = "~~~~~~~~  \n         \n";
+    let selection2 = "          \n  ~      \n";
+    let selection3 = "          \n       ~ \n";
+    sm.new_source_file(Path::new("blork.rs").to_owned().into(), inputtext.to_owned());
+    let call = span_from_selection(inputtext, selection1);
+    let x = span_from_selection(inputtext, selection2);
+    let y = span_from_selection(inputtext, selection3);
+
+    let extended = sm.span_extend_over_next_char(call, &[';']);
+    assert_eq!(sm.span_to_snippet(extended), Ok("foo(bar) ;".to_string()));
+    let extended = sm.span_extend_over_next_char(x, &[';', ',']);
+    assert_eq!(sm.span_to_snippet(extended), Ok("x  ,".to_string()));
+
+    // Nothing is done if the next non-whitespace character isn't one of `chars`.
+    assert_eq!(sm.span_extend_over_next_char(y, &[';', ',']), y);
+    assert_eq!(sm.span_extend_over_next_char(call, &[',']), call);
+}
+
+/// Tests that `span_content_hash` only depends on the text under the span.
+#[test]
+fn span_content_hash() {
+    let sm = init_source_map();
+    let blork_line = Span::with_root_ctxt(BytePos(12), BytePos(23));
+    let blork2_line = Span::with_root_ctxt(BytePos(37), BytePos(48));
+    let blork2_first = Span::with_root_ctxt(BytePos(25), BytePos(36));
+
+    let hash = sm.span_content_hash(blork_line).unwrap();
+    assert_eq!(sm.span_content_hash(blork_line), Ok(hash));
+    assert_eq!(sm.span_content_hash(blork2_line), Ok(hash));
+    assert_ne!(sm.span_content_hash(blork2_first), Ok(hash));
+    assert!(sm.span_content_hash(Span::with_root_ctxt(BytePos(12), BytePos(30))).is_err());
+}
+
+/// Tests that `file_and_offset` agrees with `lookup_byte_offset`.
+#[test]
+fn file_and_offset() {
+    let sm = init_source_map();
+    for pos in [BytePos(0), BytePos(23), BytePos(24), BytePos(25), BytePos(40)] {
+        let (sf, offset) = sm.file_and_offset(pos);
+        let SourceFileAndBytePos { sf: expected_sf, pos: expected_offset } =
+            sm.lookup_byte_offset(pos);
+        assert_eq!(sf.start_pos, expected_sf.start_pos);
+        assert_eq!(offset, expected_offset);
+    }
+    assert_eq!(sm.file_and_offset(BytePos(40)).1, BytePos(15));
+}
+
+/// Tests `clip_span_to_lines` for overlapping and non-overlapping line ranges.
+#[test]
+fn clip_span_to_lines() {
+    let sm = SourceMap::new(FilePathMapping::empty());
+    let inputtext = "aaa\nbbb\nccc\nddd\n";
+    let selection1 = " ~~\n~~~\n~~~\n~~ \n";
+    let selection2 = " ~ \n   \n   \n   \n";
+    sm.new_source_file(Path::new("blork.rs").to_owned().into(), inputtext.to_owned());
+    let span = span_from_selection(inputtext, selection1);
+    let short = span_from_selection(inputtext, selection2);
+
+    let clipped = sm.clip_span_to_lines(span, 1..3).unwrap();
+    assert_eq!(sm.span_to_snippet(clipped), Ok("bbb\nccc\n".to_string()));
+    let clipped = sm.clip_span_to_lines(span, 3..10).unwrap();
+    assert_eq!(sm.span_to_snippet(clipped), Ok("dd".to_string()));
+    let clipped = sm.clip_span_to_lines(span, 0..1).unwrap();
+    assert_eq!(sm.span_to_snippet(clipped), Ok("aa\n".to_string()));
+
+    assert_eq!(sm.clip_span_to_lines(short, 2..4), None);
+    assert_eq!(sm.clip_span_to_lines(span, 4..6), None);
+    assert_eq!(sm.clip_span_to_lines(span, 2..2), None);
+}
+
+/// Tests `SourceMap::line_begin_pos` at the start and in the middle of lines, and in an empty
+/// file.
+#[test]
+fn line_begin_pos() {
+    let sm = init_source_map();
+
+    assert_eq!(sm.line_begin_pos(BytePos(0)), Some(BytePos(0)));
+    assert_eq!(sm.line_begin_pos(BytePos(12)), Some(BytePos(12)));
+    assert_eq!(sm.line_begin_pos(BytePos(18)), Some(BytePos(12)));
+    assert_eq!(sm.line_begin_pos(BytePos(24)), None);
+    assert_eq!(sm.line_begin_pos(BytePos(25)), Some(BytePos(25)));
+    assert_eq!(sm.line_begin_pos(BytePos(30)), Some(BytePos(25)));
+}
+
+/// Tests `span_to_line_range_string` for single-line and multi-line spans.
+#[test]
+fn span_to_line_range_string() {
+    let sm = init_source_map();
+
+    let single = Span::with_root_ctxt(BytePos(14), BytePos(20));
+    assert_eq!(sm.span_to_line_range_string(single), "blork.rs:2");
+    let multi = Span::with_root_ctxt(BytePos(5), BytePos(20));
+    assert_eq!(sm.span_to_line_range_string(multi), "blork.rs:1-2");
+    let blork2 = Span::with_root_ctxt(BytePos(25), BytePos(30));
+    assert_eq!(sm.span_to_line_range_string(blork2), "blork2.rs:1");
+    assert_eq!(sm.span_to_line_range_string(DUMMY_SP), "no-location");
+}
+
+/// Tests `span_to_collapsed_snippet` for spans with and without lines to omit.
+#[test]
+fn span_to_collapsed_snippet() {
+    let sm = SourceMap::new(FilePathMapping::empty());
+    let file = sm.add_test_file("blork.rs", "fn f() {\n    a();\n    b();\n    c();\n    d();\n}");
+    let whole = Span::with_root_ctxt(file.start_pos, file.end_position());
+    let short = Span::with_root_ctxt(file.start_pos, file.start_pos + BytePos(17));
+
+    assert_eq!(
+        sm.span_to_collapsed_snippet(whole, 2, 2),
+        Ok("fn f() {\n    a();\n...\n    d();\n}".to_string())
+    );
+    assert_eq!(sm.span_to_collapsed_snippet(whole, 1, 0), Ok("fn f() {\n...".to_string()));
+    assert_eq!(sm.span_to_collapsed_snippet(whole, 3, 3), sm.span_to_snippet(whole));
+    assert_eq!(sm.span_to_collapsed_snippet(short, 1, 1), Ok("fn f() {\n    a();".to_string()));
+}
+
+/// Tests that `span_to_original_string` reports positions in doctests in terms of the file
+/// containing the doctest.
+#[test]
+fn span_to_original_string() {
+    let sm = init_source_map();
+    let doctest = sm.new_source_file(
+        FileName::doc_test_source_code(PathBuf::from("src/lib.rs"), 10),
+        "fn main() {\n    x\n}".to_string(),
+    );
+
+    let x = Span::with_root_ctxt(doctest.start_pos + BytePos(16), doctest.start_pos + BytePos(17));
+    assert_eq!(sm.span_to_original_string(x), "src/lib.rs:12:5");
+    assert_eq!(sm.span_to_diagnostic_string(x), "src/lib.rs:2:5: 2:6");
+
+    let blork = Span::with_root_ctxt(BytePos(14), BytePos(20));
+    assert_eq!(sm.span_to_original_string(blork), "blork.rs:2:3");
+}
+
+/// Tests that `lookup_external_pos` maps doctest positions back to their original file and
+/// ignores positions in ordinary files.
+#[test]
+fn lookup_external_pos() {
+    let sm = init_source_map();
+    let doctest = sm.new_source_file(
+        FileName::doc_test_source_code(PathBuf::from("src/lib.rs"), 10),
+        "fn main() {\n    x\n}".to_string(),
+    );
+
+    assert_eq!(
+        sm.lookup_external_pos(doctest.start_pos + BytePos(16)),
+        Some((PathBuf::from("src/lib.rs"), 12, CharPos(4)))
+    );
+    assert_eq!(
+        sm.lookup_external_pos(doctest.start_pos),
+        Some((PathBuf::from("src/lib.rs"), 11, CharPos(0)))
+    );
+    assert_eq!(sm.lookup_external_pos(BytePos(14)), None);
+}
+
+/// Tests `line_indent_span` for tab-indented, space-indented, and blank lines.
+#[test]
+fn line_indent_span() {
+    let sm = SourceMap::new(FilePathMapping::empty());
+    let file = sm.add_test_file("blork.rs", "\t\ttabs\n  spaces\n \t \nnone");
+    let pos = |offset: u32| file.start_pos + BytePos(offset);
+
+    assert_eq!(sm.line_indent_span(pos(4)), Some(Span::with_root_ctxt(pos(0), pos(2))));
+    assert_eq!(sm.line_indent_span(pos(7)), Some(Span::with_root_ctxt(pos(7), pos(9))));
+    assert_eq!(sm.line_indent_span(pos(17)), Some(Span::with_root_ctxt(pos(16), pos(19))));
+    assert_eq!(sm.line_indent_span(pos(22)), Some(Span::with_root_ctxt(pos(20), pos(20))));
+}
+
+/// Tests that `for_each_span_snippet` passes the right snippets and stops when asked to.
+#[test]
+fn for_each_span_snippet() {
+    let sm = init_source_map();
+    let spans = [
+        Span::with_root_ctxt(BytePos(0), BytePos(5)),
+        Span::with_root_ctxt(BytePos(12), BytePos(30)),
+        Span::with_root_ctxt(BytePos(37), BytePos(43)),
+        Span::with_root_ctxt(BytePos(25), BytePos(30)),
+    ];
+
+    let mut seen = vec![];
+    sm.for_each_span_snippet(&spans, |sp, snippet| {
+        seen.push((sp, snippet.to_string()));
+        true
+    });
+    assert_eq!(
+        seen,
+        [
+            (spans[0], "first".to_string()),
+            (spans[2], "second".to_string()),
+            (spans[3], "first".to_string())
+        ]
+    );
+
+    let mut seen = vec![];
+    sm.for_each_span_snippet(&spans, |_, snippet| {
+        seen.push(snippet.to_string());
+        seen.len() < 2
+    });
+    assert_eq!(seen, ["first", "second"]);
+}
+
+/// Tests `is_whole_line_span` for spans starting or ending mid-line, and in an empty file.
+#[test]
+fn is_whole_line_span() {
+    let sm = SourceMap::new(FilePathMapping::empty());
+    let inputtext = "aaa\nbbb\nccc\nddd";
+    let whole = "    ~~~~~~~~   ";
+    let to_eof = "        ~~~~~~~";
+    let mid_lo = "  ~~~~~~~~~~   ";
+    let mid_hi = "    ~~~~~~     ";
+    sm.new_source_file(Path::new("blork.rs").to_owned().into(), inputtext.to_owned());
+
+    assert!(sm.is_whole_line_span(span_from_selection(inputtext, whole)));
+    assert!(sm.is_whole_line_span(span_from_selection(inputtext, to_eof)));
+    assert!(!sm.is_whole_line_span(span_from_selection(inputtext, mid_lo)));
+    assert!(!sm.is_whole_line_span(span_from_selection(inputtext, mid_hi)));
+
+    // An empty file has no lines, whole or not.
+    let empty = sm.new_source_file(Path::new("empty.rs").to_owned().into(), String::new());
+    assert!(!sm.is_whole_line_span(Span::with_root_ctxt(empty.start_pos, empty.start_pos)));
+}
+
+/// Tests `line_starts_in_span` for a multi-line span starting mid-line.
+#[test]
+fn line_starts_in_span() {
+    let sm = SourceMap::new(FilePathMapping::empty());
+    let inputtext = "aaa\nbbb\nccc\nddd";
+    let selection1 = "  ~~~~~~~~~    ";
+    let selection2 = "    ~~~~~~~~   ";
+    let selection3 = "     ~         ";
+    sm.new_source_file(Path::new("blork.rs").to_owned().into(), inputtext.to_owned());
+
+    let starts = |selection| sm.line_starts_in_span(span_from_selection(inputtext, selection));
+    assert_eq!(starts(selection1), [BytePos(0), BytePos(4), BytePos(8)]);
+    assert_eq!(starts(selection2), [BytePos(4), BytePos(8), BytePos(12)]);
+    assert_eq!(starts(selection3), [BytePos(4)]);
+}
+
+/// Tests `char_len_at` at ASCII and multi-byte positions.
+#[test]
+fn char_len_at() {
+    let sm = init_source_map_mbc();
+
+    // "fir€st €€€€ line." has multi-byte chars at bytes 3, 9, 12, 15 and 18.
+    assert_eq!(sm.char_len_at(BytePos(0)), 1);
+    assert_eq!(sm.char_len_at(BytePos(3)), 3);
+    assert_eq!(sm.char_len_at(BytePos(6)), 1);
+    assert_eq!(sm.char_len_at(BytePos(18)), 3);
+    // "first line€€.\n€ second line" starts at 40, its first "€" at byte 10.
+    assert_eq!(sm.char_len_at(BytePos(50)), 3);
+    assert_eq!(sm.char_len_at(BytePos(49)), 1);
+}
+
+/// Tests that `loc_to_bytepos` undoes `lookup_char_pos`, including in the presence of multi-byte
+/// chars.
+#[test]
+fn loc_to_bytepos() {
+    let sm = init_source_map_mbc();
+    let ends: Vec<_> = sm.files().iter().map(|sf| sf.end_position()).collect();
+
+    // Every char boundary of both files, i.e. every position not inside a "€".
+    let mut pos = BytePos(0);
+    for end in ends {
+        while pos <= end {
+            assert_eq!(sm.loc_to_bytepos(&sm.lookup_char_pos(pos)), pos);
+            pos = pos + BytePos::from_usize(sm.char_len_at(pos));
+        }
+    }
+}
+
+/// Tests `coalesce_spans` with overlapping, touching and disjoint spans.
+#[test]
+fn coalesce_spans() {
+    let sm = init_source_map();
+    let sp = |lo: u32, hi: u32| Span::with_root_ctxt(BytePos(lo), BytePos(hi));
+
+    // Overlapping, out of order.
+    assert_eq!(sm.coalesce_spans(&[sp(4, 9), sp(0, 5)]), [sp(0, 9)]);
+    // Touching.
+    assert_eq!(sm.coalesce_spans(&[sp(0, 5), sp(5, 9)]), [sp(0, 9)]);
+    // Contained.
+    assert_eq!(sm.coalesce_spans(&[sp(0, 9), sp(2, 3)]), [sp(0, 9)]);
+    // Disjoint.
+    assert_eq!(
+        sm.coalesce_spans(&[sp(12, 15), sp(0, 5), sp(6, 9)]),
+        [sp(0, 5), sp(6, 9), sp(12, 15)]
+    );
+    assert!(sm.coalesce_spans(&[]).is_empty());
+}
+
+/// Tests `Span::expn_callee_name` for a span with and without expansion info.
+#[test]
+fn expn_callee_name() {
+    create_default_session_globals_then(|| {
+        let call_site = Span::with_root_ctxt(BytePos(0), BytePos(5));
+        let sp = Span::with_root_ctxt(BytePos(12), BytePos(18));
+        assert_eq!(sp.expn_callee_name(), None);
+        let expanded = expand_at(sp, call_site, "vec");
+        assert_eq!(expanded.expn_callee_name(), Some(Symbol::intern("vec")));
+    })
+}
+
+/// Tests that `filenames` lists every file in the order it was added.
+#[test]
+fn filenames() {
+    let sm = init_source_map();
+    let expected: Vec<FileName> = ["blork.rs", "empty.rs", "blork2.rs"]
+        .into_iter()
+        .map(|name| PathBuf::from(name).into())
+        .collect();
+    assert_eq!(sm.filenames(), expected);
+}
+
+/// Tests `span_to_line_end` in the middle and at the end of lines.
+#[test]
+fn span_to_line_end() {
+    let sm = init_source_map();
+
+    // "first line.\nsecond line"
+    let rest = sm.span_to_line_end(BytePos(6));
+    assert_eq!(sm.span_to_snippet(rest), Ok("line.".to_string()));
+    assert_eq!(sm.span_to_line_end(BytePos(11)), Span::with_root_ctxt(BytePos(11), BytePos(11)));
+    let rest = sm.span_to_line_end(BytePos(19));
+    assert_eq!(sm.span_to_snippet(rest), Ok("line".to_string()));
+    assert_eq!(sm.span_to_line_end(BytePos(23)), Span::with_root_ctxt(BytePos(23), BytePos(23)));
+}
+
+/// Tests `span_from_line_start` at the start and in the middle of lines.
+#[test]
+fn span_from_line_start() {
+    let sm = init_source_map();
+
+    // "first line.\nsecond line"
+    assert_eq!(sm.span_from_line_start(BytePos(0)), Span::with_root_ctxt(BytePos(0), BytePos(0)));
+    let head = sm.span_from_line_start(BytePos(5));
+    assert_eq!(sm.span_to_snippet(head), Ok("first".to_string()));
+    assert_eq!(
+        sm.span_from_line_start(BytePos(12)),
+        Span::with_root_ctxt(BytePos(12), BytePos(12))
+    );
+    let head = sm.span_from_line_start(BytePos(18));
+    assert_eq!(sm.span_to_snippet(head), Ok("second".to_string()));
+}
+
+/// Tests `is_after_line_comment` for positions before, inside and after a comment marker.
+#[test]
+fn is_after_line_comment() {
+    let sm = SourceMap::new(FilePathMapping::empty());
+    let file = sm.add_test_file("blork.rs", "let x = 1; // one\n// two\nfoo");
+    let pos = |offset: u32| file.start_pos + BytePos(offset);
+
+    assert!(!sm.is_after_line_comment(pos(4), "//"));
+    assert!(!sm.is_after_line_comment(pos(11), "//"));
+    assert!(!sm.is_after_line_comment(pos(12), "//"));
+    assert!(sm.is_after_line_comment(pos(13), "//"));
+    assert!(sm.is_after_line_comment(pos(17), "//"));
+    assert!(sm.is_after_line_comment(pos(22), "//"));
+    // The comment on the previous line doesn't carry over.
+    assert!(!sm.is_after_line_comment(pos(26), "//"));
+    assert!(sm.is_after_line_comment(pos(7), "="));
+}
+
+/// Tests that `SourceMap::count_lines` sums the line counts of all files.
+#[test]
+fn count_lines() {
+    let sm = SourceMap::new(FilePathMapping::empty());
+    assert_eq!(sm.count_lines(), 0);
+    sm.add_test_file("blork.rs", "a\nb\nc\n");
+    sm.add_test_file("empty.rs", "");
+    sm.add_test_file("blork2.rs", "a\nb");
+    assert_eq!(sm.count_lines(), 5);
+}
+
+/// Tests `trailing_whitespace_span` for lines ending in spaces, tabs, and neither.
+#[test]
+fn trailing_whitespace_span() {
+    let sm = SourceMap::new(FilePathMapping::empty());
+    let file = sm.add_test_file("blork.rs", "spaces  \ntabs\t\t\nnone\n \t");
+    let pos = |offset: u32| file.start_pos + BytePos(offset);
+
+    assert_eq!(sm.trailing_whitespace_span(&file, 0), Some(Span::with_root_ctxt(pos(6), pos(8))));
+    assert_eq!(sm.trailing_whitespace_span(&file, 1), Some(Span::with_root_ctxt(pos(13), pos(15))));
+    assert_eq!(sm.trailing_whitespace_span(&file, 2), None);
+    assert_eq!(sm.trailing_whitespace_span(&file, 3), Some(Span::with_root_ctxt(pos(21), pos(23))));
+    assert_eq!(sm.trailing_whitespace_span(&file, 4), None);
+}
+
+/// Tests that `file_src_changed` detects a file whose source was replaced since an earlier
+/// `SourceMap` was built.
+#[test]
+fn file_src_changed() {
+    let old = SourceMap::new(FilePathMapping::empty());
+    let token = old.add_test_file("blork.rs", "first line.\nsecond line").src_hash;
+    let name: FileName = PathBuf::from("blork.rs").into();
+    assert!(!old.file_src_changed(&name, token));
+
+    let same = init_source_map();
+    assert!(!same.file_src_changed(&name, token));
+    let replaced = SourceMap::new(FilePathMapping::empty());
+    replaced.add_test_file("blork.rs", "first line.\nsecond line, edited");
+    assert!(replaced.file_src_changed(&name, token));
+    let removed = SourceMap::new(FilePathMapping::empty());
+    assert!(removed.file_src_changed(&name, token));
+}
+
+/// Tests the underlines produced by `make_caret`.
+#[test]
+fn make_caret() {
+    assert_eq!(super::make_caret(3, 4), "   ^~~~");
+    assert_eq!(super::make_caret(0, 1), "^");
+    assert_eq!(super::make_caret(2, 0), "  ^");
+}
+
+/// Tests that `try_lookup_char_pos` rejects positions past the last file.
+#[test]
+fn try_lookup_char_pos() {
+    let sm = SourceMap::new(FilePathMapping::empty());
+    assert!(sm.try_lookup_char_pos(BytePos(0)).is_err());
+
+    let sm = init_source_map();
+    let loc = sm.try_lookup_char_pos(BytePos(30)).unwrap();
+    assert_eq!(loc.file.name, FileName::from(PathBuf::from("blork2.rs")));
+    assert_eq!((loc.line, loc.col), (1, CharPos(5)));
+    assert!(sm.try_lookup_char_pos(BytePos(48)).is_ok());
+    assert!(sm.try_lookup_char_pos(BytePos(49)).is_err());
+}
+
+/// Tests `grow_span_lines` near the top and the bottom of a file.
+#[test]
+fn grow_span_lines() {
+    let sm = SourceMap::new(FilePathMapping::empty());
+    let inputtext = "aaa\nbbb\nccc\nddd\neee";
+    let selection1 = "     ~             ";
+    let selection2 = "             ~     ";
+    sm.new_source_file(Path::new("blork.rs").to_owned().into(), inputtext.to_owned());
+    let grown = |selection, up, down| {
+        let sp = sm.grow_span_lines(span_from_selection(inputtext, selection), up, down);
+        sm.span_to_snippet(sp).unwrap()
+    };
+
+    assert_eq!(grown(selection1, 0, 0), "bbb\n");
+    assert_eq!(grown(selection1, 1, 1), "aaa\nbbb\nccc\n");
+    assert_eq!(grown(selection1, 5, 0), "aaa\nbbb\n");
+    assert_eq!(grown(selection2, 1, 1), "ccc\nddd\neee");
+    assert_eq!(grown(selection2, 0, 5), "ddd\neee");
+}
+
+/// Tests `count_char_in_span` over an argument list.
+#[test]
+fn count_char_in_span() {
+    let sm = SourceMap::new(FilePathMapping::empty());
+    let file = sm.add_test_file("blork.rs", "f(a, b, (c, d), e);");
+    let args = Span::with_root_ctxt(file.start_pos + BytePos(2), file.start_pos + BytePos(17));
+
+    assert_eq!(sm.count_char_in_span(args, ','), Ok(4));
+    assert_eq!(sm.count_char_in_span(args, ';'), Ok(0));
+    assert_eq!(sm.count_char_in_span(args.shrink_to_lo(), ','), Ok(0));
+}
+
+/// Tests `spans_eq_ignoring_ws` for snippets differing in whitespace and in tokens.
+#[test]
+fn spans_eq_ignoring_ws() {
+    let sm = SourceMap::new(FilePathMapping::empty());
+    let file = sm.add_test_file("blork.rs", "a + b\n  a  +\n\tb\na + c");
+    let span = |lo: u32, hi: u32| {
+        Span::with_root_ctxt(file.start_pos + BytePos(lo), file.start_pos + BytePos(hi))
+    };
+    let (first, second, third) = (span(0, 5), span(6, 15), span(16, 21));
+
+    assert!(sm.spans_eq_ignoring_ws(first, second));
+    assert!(sm.spans_eq_ignoring_ws(second, first));
+    assert!(!sm.spans_eq_ignoring_ws(first, third));
+    assert!(!sm.spans_eq_ignoring_ws(first, span(0, 3)));
+}
+
+/// Tests that `enclosing_line` agrees with `lookup_char_pos`, `get_line` and `span_to_snippet`.
+#[test]
+fn enclosing_line() {
+    let sm = init_source_map();
+
+    for pos in [BytePos(0), BytePos(5), BytePos(11), BytePos(18), BytePos(30)] {
+        let (line, span, text) = sm.enclosing_line(pos).unwrap();
+        let loc = sm.lookup_char_pos(pos);
+        assert_eq!(line, loc.line);
+        assert_eq!(loc.file.get_line(line - 1).as_deref(), Some(&*text));
+        assert_eq!(sm.span_to_snippet(span).as_deref(), Ok(&*text));
+        assert!(span.lo() <= pos && pos <= span.hi());
+    }
+    assert_eq!(sm.enclosing_line(BytePos(24)), None);
+}
+
+/// Tests that registering a file name a second time returns the existing `SourceFile` instead of
+/// adding a new one.
+#[test]
+fn same_name_registers_one_file() {
+    let sm = SourceMap::new(FilePathMapping::empty());
+    let first = sm.add_test_file("blork.rs", "first line.\nsecond line");
+    let second = sm.add_test_file("blork.rs", "first line.\nsecond line");
+
+    assert!(Lrc::ptr_eq(&first, &second));
+    assert_eq!(sm.files().len(), 1);
+}
+
+/// Tests that `lookup_line_index` is one less than the line reported by `lookup_char_pos`.
+#[test]
+fn lookup_line_index() {
+    let sm = init_source_map();
+
+    for pos in [0, 5, 11, 12, 23, 25, 40] {
+        let pos = BytePos(pos);
+        assert_eq!(sm.lookup_line_index(pos), Some(sm.lookup_char_pos(pos).line - 1));
+    }
+    assert_eq!(sm.lookup_line_index(BytePos(12)), Some(1));
+    assert_eq!(sm.lookup_line_index(BytePos(24)), None);
+}
+
+/// Tests `span_focus_window` for spans near the start, in the middle and near the end of a line.
+#[test]
+fn span_focus_window() {
+    let sm = SourceMap::new(FilePathMapping::empty());
+    let file = sm.add_test_file("blork.rs", "0123456789abcdefghij\nnext");
+    let span = |lo: u32, hi: u32| {
+        Span::with_root_ctxt(file.start_pos + BytePos(lo), file.start_pos + BytePos(hi))
+    };
+
+    assert_eq!(sm.span_focus_window(span(1, 2), 3), Some(("01234…".to_string(), 1)));
+    assert_eq!(sm.span_focus_window(span(10, 12), 3), Some(("…789abcde…".to_string(), 4)));
+    assert_eq!(sm.span_focus_window(span(18, 20), 3), Some(("…fghij".to_string(), 4)));
+    assert_eq!(sm.span_focus_window(span(2, 4), 30), Some(("0123456789abcdefghij".to_string(), 2)));
+}
+
+/// Tests `is_well_formed_span` for valid, backwards, cross-file, mid-char and out-of-range spans.
+#[test]
+fn is_well_formed_span() {
+    let sm = init_source_map_mbc();
+    let span = |lo: u32, hi: u32| Span::with_root_ctxt(BytePos(lo), BytePos(hi));
+
+    // "fir€st €€€€ line." has multi-byte chars at bytes 3, 9, 12, 15 and 18.
+    assert!(sm.is_well_formed_span(span(0, 6)));
+    assert!(sm.is_well_formed_span(span(45, 45)));
+    // `Span::new` swaps backwards endpoints, so a backwards span is well formed once it exists.
+    assert!(sm.is_well_formed_span(span(6, 0)));
+    assert!(!sm.is_well_formed_span(span(30, 45)));
+    assert!(!sm.is_well_formed_span(span(4, 9)));
+    assert!(!sm.is_well_formed_span(span(0, 10)));
+    assert!(!sm.is_well_formed_span(span(60, 200)));
+    assert!(!SourceMap::new(FilePathMapping::empty()).is_well_formed_span(span(0, 0)));
+}
+
+/// Tests `get_source_files` with a mix of known and unknown names.
+#[test]
+fn get_source_files() {
+    let sm = init_source_map();
+    let names: Vec<FileName> = ["blork2.rs", "missing.rs", "blork.rs"]
+        .into_iter()
+        .map(|name| PathBuf::from(name).into())
+        .collect();
+
+    let found = sm.get_source_files(&names);
+    assert_eq!(found.len(), 3);
+    assert_eq!(found[0].as_ref().map(|sf| sf.start_pos), Some(BytePos(25)));
+    assert!(found[1].is_none());
+    assert_eq!(found[2].as_ref().map(|sf| sf.start_pos), Some(BytePos(0)));
+}
+
+/// Tests `named_line_span` for valid lines, an unknown file and out-of-range lines.
+#[test]
+fn named_line_span() {
+    let sm = init_source_map();
+    let name = |name: &str| FileName::from(PathBuf::from(name));
+    let span = |lo, hi| Span::with_root_ctxt(BytePos(lo), BytePos(hi));
+
+    assert_eq!(sm.named_line_span(&name("blork.rs"), 1), Some(span(0, 11)));
+    assert_eq!(sm.named_line_span(&name("blork.rs"), 2), Some(span(12, 23)));
+    assert_eq!(sm.named_line_span(&name("blork2.rs"), 2), Some(span(37, 48)));
+    assert_eq!(sm.named_line_span(&name("missing.rs"), 1), None);
+    assert_eq!(sm.named_line_span(&name("blork.rs"), 0), None);
+    assert_eq!(sm.named_line_span(&name("blork.rs"), 3), None);
+}
+
+/// Tests that `line_display_width` is the display column just past the last char of the line,
+/// which is where right-aligned annotations start.
+#[test]
+fn line_display_width_is_end_column() {
+    let sm = SourceMap::new(FilePathMapping::empty());
+    let file = sm.add_test_file("blork.rs", "\t日\tx\n  本b");
+
+    for line in 0..2 {
+        let end =
+            file.line_bounds(line).start + BytePos::from_usize(file.get_line(line).unwrap().len());
+        let width = sm.line_display_width(&file, line);
+        assert_eq!(width, Some(sm.lookup_char_pos(end).col_display));
+    }
+    assert_eq!(sm.line_display_width(&file, 0), Some(11));
+    assert_eq!(sm.line_display_width(&file, 1), Some(5));
+}
+
+/// Tests `chars_around` near file boundaries and next to multi-byte chars.
+#[test]
+fn chars_around() {
+    let sm = init_source_map_mbc();
+
+    // "fir€st €€€€ line.\nsecond line" is followed by "first line€€.\n€ second line" at 40.
+    assert_eq!(sm.chars_around(BytePos(0), 3, 2), Ok("fi".to_string()));
+    assert_eq!(sm.chars_around(BytePos(6), 2, 1), Ok("r€s".to_string()));
+    assert_eq!(sm.chars_around(BytePos(3), 1, 1), Ok("r€".to_string()));
+    assert_eq!(sm.chars_around(BytePos(39), 4, 3), Ok("line".to_string()));
+    assert_eq!(sm.chars_around(BytePos(40), 3, 1), Ok("f".to_string()));
+    assert!(sm.chars_around(BytePos(4), 1, 1).is_err());
+}
+
+/// Tests `same_file` within a file, across files, and at file boundaries.
+#[test]
+fn same_file() {
+    let sm = init_source_map();
+
+    assert!(sm.same_file(BytePos(0), BytePos(20)));
+    assert!(sm.same_file(BytePos(0), BytePos(23)));
+    assert!(!sm.same_file(BytePos(23), BytePos(24)));
+    assert!(!sm.same_file(BytePos(24), BytePos(25)));
+    assert!(sm.same_file(BytePos(25), BytePos(48)));
+    assert!(!sm.same_file(BytePos(5), BytePos(30)));
+}
+
+/// Tests that `span_to_bytes` returns the bytes of the snippet, even for a partial char.
+#[test]
+fn span_to_bytes() {
+    let sm = init_source_map_mbc();
+
+    let span = Span::with_root_ctxt(BytePos(0), BytePos(9));
+    let snippet = sm.span_to_snippet(span).unwrap();
+    assert_eq!(sm.span_to_bytes(span), Ok(snippet.into_bytes()));
+    // Only the first two bytes of "€".
+    let partial = Span::with_root_ctxt(BytePos(3), BytePos(5));
+    assert_eq!(sm.span_to_bytes(partial), Ok(vec![0xe2, 0x82]));
+}
+
+/// Tests `span_ends_at_eof` for files ending with and without a newline.
+#[test]
+fn span_ends_at_eof() {
+    let sm = SourceMap::new(FilePathMapping::empty());
+    let newline = sm.add_test_file("newline.rs", "fn f() {}\n");
+    let no_newline = sm.add_test_file("no_newline.rs", "fn f() {}");
+    let empty = sm.add_test_file("empty.rs", "");
+    let whole = |sf: &SourceFile| Span::with_root_ctxt(sf.start_pos, sf.end_position());
+
+    assert!(!sm.span_ends_at_eof(whole(&newline)));
+    assert!(sm.span_ends_at_eof(whole(&no_newline)));
+    assert!(!sm.span_ends_at_eof(whole(&no_newline).with_hi(no_newline.start_pos + BytePos(8))));
+    assert!(!sm.span_ends_at_eof(whole(&empty)));
+}
+
+/// Tests that the position just past the end of the last file, e.g. the `hi` of a span ending at
+/// EOF, resolves to the end of that file's last line.
+#[test]
+fn lookup_one_past_last_file() {
+    let sm = init_source_map();
+    let end = sm.files().last().unwrap().end_position();
+    assert_eq!(end, BytePos(48));
+
+    let loc = sm.lookup_char_pos(end);
+    assert_eq!(loc.file.name, FileName::from(PathBuf::from("blork2.rs")));
+    assert_eq!((loc.line, loc.col), (2, CharPos(11)));
+    let sp = Span::with_root_ctxt(BytePos(44), end);
+    assert_eq!(sm.span_to_snippet(sp), Ok("line".to_string()));
+}
+
+/// Tests the gutter alignment and caret lines of `render_span_with_gutter`, including spans
+/// ending after a newline or covering a blank line, and a span in an empty file.
+#[test]
+fn render_span_with_gutter() {
+    let sm = SourceMap::new(FilePathMapping::empty());
+    let file = sm.add_test_file("blork.rs", &format!("{}let x = 1;\nfoo(x);\n", "a\n".repeat(8)));
+    let span = Span::with_root_ctxt(file.start_pos + BytePos(20), file.start_pos + BytePos(30));
+
+    assert_eq!(
+        sm.render_span_with_gutter(span, 1, 1).unwrap(),
+        " 8 | a\n 9 | let x = 1;\n   |     ^~~~~~\n10 | foo(x);\n   | ^~~\n"
+    );
+    assert_eq!(
+        sm.render_span_with_gutter(span, 0, 0).unwrap(),
+        " 9 | let x = 1;\n   |     ^~~~~~\n10 | foo(x);\n   | ^~~\n"
+    );
+
+    // A span ending right after a newline doesn't underline the following line.
+    let span = Span::with_root_ctxt(file.start_pos + BytePos(16), file.start_pos + BytePos(27));
+    assert_eq!(sm.render_span_with_gutter(span, 0, 0).unwrap(), "9 | let x = 1;\n  | ^~~~~~~~~~\n");
+
+    // A blank line inside the span has nothing to underline.
+    let file = sm.add_test_file("blank.rs", "fn f() {\n\n}\n");
+    let span = Span::with_root_ctxt(file.start_pos, file.start_pos + BytePos(11));
+    assert_eq!(
+        sm.render_span_with_gutter(span, 0, 0).unwrap(),
+        "1 | fn f() {\n  | ^~~~~~~~\n2 |\n3 | }\n  | ^\n"
+    );
+
+    // There is nothing to render in an empty file, even one that doesn't start at offset 0.
+    let file = sm.add_test_file("empty.rs", "");
+    let span = Span::with_root_ctxt(file.start_pos, file.start_pos);
+    assert_eq!(sm.render_span_with_gutter(span, 1, 1).unwrap(), "");
+}
+
+/// Tests `prev_token_span` after tokens, after whitespace, and on blank lines.
+#[test]
+fn prev_token_span() {
+    let sm = SourceMap::new(FilePathMapping::empty());
+    let file = sm.add_test_file("blork.rs", "foo(bar)  baz;\n  \nx");
+    let span = |lo: u32, hi: u32| {
+        Span::with_root_ctxt(file.start_pos + BytePos(lo), file.start_pos + BytePos(hi))
+    };
+    let prev = |pos: u32| sm.prev_token_span(file.start_pos + BytePos(pos));
+
+    assert_eq!(prev(10), span(0, 8));
+    assert_eq!(prev(13), span(10, 13));
+    assert_eq!(prev(14), span(10, 14));
+    assert_eq!(prev(17), span(15, 15));
+    assert_eq!(prev(18), span(18, 18));
+}
+
+/// Tests `trailing_blank_lines_span` for files ending in zero, one and several blank lines.
+#[test]
+fn trailing_blank_lines_span() {
+    let sm = SourceMap::new(FilePathMapping::empty());
+    let trailing = |name: &str, src: &str| {
+        let file = sm.add_test_file(name, src);
+        sm.trailing_blank_lines_span(&file).map(|sp| sm.span_to_snippet(sp).unwrap())
+    };
+
+    assert_eq!(trailing("none.rs", "fn f() {}\n"), None);
+    assert_eq!(trailing("no_newline.rs", "fn f() {}  "), None);
+    assert_eq!(trailing("one.rs", "fn f() {}\n\n"), Some("\n".to_string()));
+    assert_eq!(trailing("several.rs", "fn f() {}\n\n  \n\t\n"), Some("\n  \n\t\n".to_string()));
+    assert_eq!(trailing("blank.rs", "\n\n"), Some("\n\n".to_string()));
+}
+
+/// Tests `cmp_spans` for spans in different files and at file boundaries.
+#[test]
+fn cmp_spans() {
+    use std::cmp::Ordering;
+
+    let sm = init_source_map();
+    let span = |lo: u32, hi: u32| Span::with_root_ctxt(BytePos(lo), BytePos(hi));
+
+    assert_eq!(sm.cmp_spans(span(12, 23), span(25, 30)), Ordering::Less);
+    assert_eq!(sm.cmp_spans(span(25, 30), span(0, 5)), Ordering::Greater);
+    assert_eq!(sm.cmp_spans(span(0, 5), span(0, 5)), Ordering::Equal);
+    assert_eq!(sm.cmp_spans(span(0, 5), span(0, 6)), Ordering::Less);
+    // The end of blork.rs comes before the empty file, which comes before blork2.rs.
+    assert_eq!(sm.cmp_spans(span(23, 23), span(24, 24)), Ordering::Less);
+    assert_eq!(sm.cmp_spans(span(24, 24), span(25, 25)), Ordering::Less);
+
+    let mut spans = vec![span(25, 30), span(23, 23), span(0, 5), span(24, 24)];
+    spans.sort_by(|&a, &b| sm.cmp_spans(a, b));
+    assert_eq!(spans, [span(0, 5), span(23, 23), span(24, 24), span(25, 30)]);
+}
+
+/// Tests `span_to_snippet_expanded` with leading and interior tabs.
+#[test]
+fn span_to_snippet_expanded() {
+    let sm = SourceMap::new(FilePathMapping::empty());
+    let file = sm.add_test_file("blork.rs", "\tlet x\t= 1;\nab\tc\n");
+    let span = |lo: u32, hi: u32| {
+        Span::with_root_ctxt(file.start_pos + BytePos(lo), file.start_pos + BytePos(hi))
+    };
+
+    assert_eq!(sm.span_to_snippet_expanded(span(0, 11), 4), Ok("    let x   = 1;".to_string()));
+    // The interior tab still ends at column 12 when the span starts after the leading tab.
+    assert_eq!(sm.span_to_snippet_expanded(span(1, 8), 4), Ok("let x   =".to_string()));
+    assert_eq!(sm.span_to_snippet_expanded(span(6, 7), 4), Ok("   ".to_string()));
+    assert_eq!(
+        sm.span_to_snippet_expanded(span(0, 16), 8),
+        Ok("        let x   = 1;\nab      c".to_string())
+    );
+}
+
+/// Tests `enclosing_paragraph` in the middle of a file, at its start and end, and on blank lines.
+#[test]
+fn enclosing_paragraph() {
+    let sm = SourceMap::new(FilePathMapping::empty());
+    let file = sm.add_test_file("blork.rs", "one\ntwo\n\nthree\nfour\n  \nfive");
+    let paragraph = |pos: u32| {
+        let sp = sm.enclosing_paragraph(file.start_pos + BytePos(pos));
+        sm.span_to_snippet(sp).unwrap()
+    };
+
+    assert_eq!(paragraph(0), "one\ntwo");
+    assert_eq!(paragraph(5), "one\ntwo");
+    assert_eq!(paragraph(8), "");
+    assert_eq!(paragraph(12), "three\nfour");
+    assert_eq!(paragraph(17), "three\nfour");
+    assert_eq!(paragraph(21), "");
+    assert_eq!(paragraph(25), "five");
+    let blank = file.start_pos + BytePos(22);
+    assert_eq!(sm.enclosing_paragraph(blank), Span::with_root_ctxt(blank, blank));
+}
+
+/// Tests `is_single_full_line` for a full line, partial lines and a two-line span.
+#[test]
+fn is_single_full_line() {
+    let sm = SourceMap::new(FilePathMapping::empty());
+    let inputtext = "aaa\nbbb\nccc";
+    let full = "    ~~~    ";
+    let last = "        ~~~";
+    let with_newline = "    ~~~~   ";
+    let partial = "     ~~    ";
+    let two_lines = "~~~~~~~    ";
+    sm.new_source_file(Path::new("blork.rs").to_owned().into(), inputtext.to_owned());
+
+    assert!(sm.is_single_full_line(span_from_selection(inputtext, full)));
+    assert!(sm.is_single_full_line(span_from_selection(inputtext, last)));
+    assert!(!sm.is_single_full_line(span_from_selection(inputtext, with_newline)));
+    assert!(!sm.is_single_full_line(span_from_selection(inputtext, partial)));
+    assert!(!sm.is_single_full_line(span_from_selection(inputtext, two_lines)));
+}
+
+/// Tests `between_snippet` for gapped, adjacent, overlapping and cross-file spans.
+#[test]
+fn between_snippet() {
+    let sm = init_source_map();
+    let span = |lo: u32, hi: u32| Span::with_root_ctxt(BytePos(lo), BytePos(hi));
+
+    // "first line.\nsecond line"
+    assert_eq!(sm.between_snippet(span(0, 5), span(6, 10)), Ok(" ".to_string()));
+    assert_eq!(sm.between_snippet(span(0, 5), span(12, 18)), Ok(" line.\n".to_string()));
+    assert_eq!(sm.between_snippet(span(0, 5), span(5, 10)), Ok(String::new()));
+    assert_eq!(sm.between_snippet(span(0, 8), span(6, 10)), Ok(String::new()));
+    assert!(sm.between_snippet(span(0, 5), span(25, 30)).is_err());
+    // Even when `b` is in an earlier file, so `b` starts before `a` ends.
+    assert_eq!(
+        sm.between_snippet(span(25, 30), span(0, 5)),
+        Err(SpanSnippetError::DistinctSources(Box::new(DistinctSources {
+            begin: (PathBuf::from("blork2.rs").into(), BytePos(25)),
+            end: (PathBuf::from("blork.rs").into(), BytePos(0)),
+        })))
+    );
+}
+
+/// Tests `lookup_editor_col` with and without leading tabs at tab widths 4 and 8.
+#[test]
+fn lookup_editor_col() {
+    let sm = SourceMap::new(FilePathMapping::empty());
+    let file = sm.add_test_file("blork.rs", "let x;\n\tlet y;\n\t\tz;\n");
+    let col = |pos: u32, tab_width| sm.lookup_editor_col(file.start_pos + BytePos(pos), tab_width);
+
+    assert_eq!(col(0, 4), 1);
+    assert_eq!(col(4, 4), 5);
+    assert_eq!(col(4, 8), 5);
+    assert_eq!(col(7, 4), 1);
+    assert_eq!(col(12, 4), 9);
+    assert_eq!(col(12, 8), 13);
+    assert_eq!(col(17, 4), 9);
+    assert_eq!(col(17, 8), 17);
+}
+
+/// Tests `Span::expansion_depth` for spans expanded zero, one and two times.
+#[test]
+fn expansion_depth() {
+    create_default_session_globals_then(|| {
+        let outer_call = Span::with_root_ctxt(BytePos(0), BytePos(8));
+        let inner_call =
+            expand_at(Span::with_root_ctxt(BytePos(10), BytePos(18)), outer_call, "outer");
+        let expr = expand_at(Span::with_root_ctxt(BytePos(20), BytePos(25)), inner_call, "inner");
+
+        assert_eq!(outer_call.expansion_depth(), 0);
+        assert_eq!(inner_call.expansion_depth(), 1);
+        assert_eq!(expr.expansion_depth(), 2);
+    })
+}
+
+/// Tests `offsets_to_lines` with offsets on overlapping lines and outside of the file.
+#[test]
+fn offsets_to_lines() {
+    let sm = init_source_map();
+    let blork = sm.lookup_source_file(BytePos(0));
+    let blork2 = sm.lookup_source_file(BytePos(25));
+    let offsets = [BytePos(20), BytePos(3), BytePos(30), BytePos(12), BytePos(0), BytePos(40)];
+
+    assert_eq!(sm.offsets_to_lines(&blork, &offsets), [1, 2]);
+    assert_eq!(sm.offsets_to_lines(&blork2, &offsets), [1, 2]);
+    assert_eq!(sm.offsets_to_lines(&blork2, &offsets[..4]), [1]);
+    assert!(sm.offsets_to_lines(&blork, &[]).is_empty());
+}
+
+/// Tests `path_segment_span_at` with the cursor in different segments of a path.
+#[test]
+fn path_segment_span_at() {
+    let sm = SourceMap::new(FilePathMapping::empty());
+    let file = sm.add_test_file("blork.rs", "use foo::bar_baz;");
+    let segment = |pos: u32| {
+        let sp = sm.path_segment_span_at(file.start_pos + BytePos(pos));
+        sm.span_to_snippet(sp).unwrap()
+    };
+
+    assert_eq!(segment(4), "foo");
+    assert_eq!(segment(6), "foo");
+    assert_eq!(segment(7), "");
+    assert_eq!(segment(8), "");
+    assert_eq!(segment(9), "bar_baz");
+    assert_eq!(segment(13), "bar_baz");
+    assert_eq!(segment(16), "bar_baz");
+    assert_eq!(segment(1), "use");
+}
+
+/// Tests `line_starts_in_range` for ranges covering several lines.
+#[test]
+fn line_starts_in_range() {
+    let sm = SourceMap::new(FilePathMapping::empty());
+    let file = sm.add_test_file("blork.rs", "aaa\nbbb\nccc\nddd");
+    let starts = |lo: u32, hi: u32| {
+        sm.line_starts_in_range(file.start_pos + BytePos(lo), file.start_pos + BytePos(hi))
+    };
+    let pos = |offset: u32| file.start_pos + BytePos(offset);
+
+    assert_eq!(starts(2, 11), [pos(0), pos(4), pos(8)]);
+    assert_eq!(starts(4, 12), [pos(4), pos(8)]);
+    assert_eq!(starts(4, 13), [pos(4), pos(8), pos(12)]);
+    assert_eq!(starts(5, 5), [pos(4)]);
+    assert_eq!(starts(0, 15), [pos(0), pos(4), pos(8), pos(12)]);
+}
+
+/// Tests that `line_starts_in_range` rejects ranges spanning several files.
+#[test]
+#[should_panic]
+fn line_starts_in_range_across_files() {
+    let sm = init_source_map();
+    sm.line_starts_in_range(BytePos(5), BytePos(30));
+}
+
+/// Tests `has_mixed_indent` for tab-only, space-only and mixed indentation.
+#[test]
+fn has_mixed_indent() {
+    let sm = SourceMap::new(FilePathMapping::empty());
+    let file =
+        sm.add_test_file("blork.rs", "\t\ttabs\n    spaces\n\t  mixed\n  \tmixed\nnone a\t b");
+
+    assert!(!sm.has_mixed_indent(&file, 0));
+    assert!(!sm.has_mixed_indent(&file, 1));
+    assert!(sm.has_mixed_indent(&file, 2));
+    assert!(sm.has_mixed_indent(&file, 3));
+    assert!(!sm.has_mixed_indent(&file, 4));
+    assert!(!sm.has_mixed_indent(&file, 5));
+}
+
+/// Tests `span_to_selection` for a multi-line span.
+#[test]
+fn span_to_selection() {
+    let sm = init_source_map_mbc();
+    // From "€€€ line." on the first line of blork.rs to "second" on its second line.
+    let span = Span::with_root_ctxt(BytePos(12), BytePos(34));
+
+    let (anchor, active) = sm.span_to_selection(span);
+    assert_eq!(anchor, LineCol { line: 0, col: CharPos(8) });
+    assert_eq!(active, LineCol { line: 1, col: CharPos(6) });
+}
+
+/// Tests `line_with_markers` for a mid-line span, a span reaching the end of the line and a
+/// multi-line span.
+#[test]
+fn line_with_markers() {
+    let sm = init_source_map();
+    let span = |lo: u32, hi: u32| Span::with_root_ctxt(BytePos(lo), BytePos(hi));
+
+    // "first line.\nsecond line"
+    assert_eq!(sm.line_with_markers(span(6, 10)), Some("first »line«.".to_string()));
+    assert_eq!(sm.line_with_markers(span(6, 11)), Some("first »line.«".to_string()));
+    assert_eq!(sm.line_with_markers(span(19, 23)), Some("second »line«".to_string()));
+    assert_eq!(sm.line_with_markers(span(6, 18)), Some("first »line.«".to_string()));
+    assert_eq!(sm.line_with_markers(span(0, 0)), Some("»«first line.".to_string()));
+}
+
+/// Tests `indent_width` for space-only, tab-only and mixed indentation at tab widths 4 and 8.
+#[test]
+fn indent_width() {
+    let sm = SourceMap::new(FilePathMapping::empty());
+    let file = sm.add_test_file("blork.rs", "   spaces\n\t\ttabs\n  \t mixed\nnone");
+    let width = |pos: u32, tab_width| sm.indent_width(file.start_pos + BytePos(pos), tab_width);
+
+    assert_eq!(width(5, 4), 3);
+    assert_eq!(width(5, 8), 3);
+    assert_eq!(width(13, 4), 8);
+    assert_eq!(width(13, 8), 16);
+    assert_eq!(width(24, 4), 5);
+    assert_eq!(width(24, 8), 9);
+    assert_eq!(width(30, 4), 0);
+}
+
+/// Tests `span_to_snippet_lines` for single-line and multi-line spans, including CRLF content.
+#[test]
+fn span_to_snippet_lines() {
+    let sm = SourceMap::new(FilePathMapping::empty());
+    let file = sm.add_test_file("blork.rs", "let a;\r\nlet b;\r\nlet c;");
+    let span = |lo: u32, hi: u32| {
+        Span::with_root_ctxt(file.start_pos + BytePos(lo), file.start_pos + BytePos(hi))
+    };
+
+    // The `\r`s have been normalized away, so the source is "let a;\nlet b;\nlet c;".
+    assert_eq!(sm.span_to_snippet_lines(span(4, 5)), Ok(vec!["a".to_string()]));
+    assert_eq!(
+        sm.span_to_snippet_lines(span(4, 19)),
+        Ok(vec!["a;".to_string(), "let b;".to_string(), "let c".to_string()])
+    );
+    assert_eq!(sm.span_to_snippet_lines(span(0, 7)), Ok(vec!["let a;".to_string(), String::new()]));
+
+    let file = sm.add_test_file("lone_cr.rs", "a\rb\r\n");
+    let all = Span::with_root_ctxt(file.start_pos, file.end_position());
+    assert_eq!(sm.span_to_snippet_lines(all), Ok(vec!["a\rb".to_string(), String::new()]));
+}
+
+/// Tests `first_diff_offset` for equal snippets, prefixes and snippets diverging midway.
+#[test]
+fn first_diff_offset() {
+    let sm = SourceMap::new(FilePathMapping::empty());
+    let file = sm.add_test_file("blork.rs", "foo(1)\nfoo(1)\nfoo(12)\nfö(1)\nfö(2)");
+    let span = |lo: u32, hi: u32| {
+        Span::with_root_ctxt(file.start_pos + BytePos(lo), file.start_pos + BytePos(hi))
+    };
+    let (first, second, third, fourth) = (span(0, 6), span(7, 13), span(14, 21), span(22, 28));
+
+    assert_eq!(sm.first_diff_offset(first, second), Ok(None));
+    assert_eq!(sm.first_diff_offset(span(0, 3), first), Ok(None));
+    assert_eq!(sm.first_diff_offset(first, third), Ok(Some(5)));
+    assert_eq!(sm.first_diff_offset(fourth, first), Ok(Some(1)));
+    // Offsets are counted in chars, not bytes.
+    assert_eq!(sm.first_diff_offset(fourth, span(29, 35)), Ok(Some(3)));
+}
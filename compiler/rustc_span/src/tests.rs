@@ -42,3 +42,179 @@ fn check(before: &str, after: &str, expected_positions: &[u32]) {
     check("\r\r\n", "\r\n", &[2]);
     check("hello\rworld", "hello\rworld", &[]);
 }
+
+#[test]
+fn test_char_count() {
+    let sf = SourceFile::new(
+        FileName::Anon(Hash64::ZERO),
+        "a€b\n€€".to_owned(),
+        SourceFileHashAlgorithm::Sha256,
+    )
+    .unwrap();
+    assert_eq!(sf.source_len.to_usize(), 12);
+    assert_eq!(sf.char_count(), 6);
+    assert!(sf.char_count() < sf.source_len.to_usize());
+
+    let sf = SourceFile::new(
+        FileName::Anon(Hash64::ZERO),
+        "abc".to_owned(),
+        SourceFileHashAlgorithm::Sha256,
+    )
+    .unwrap();
+    assert_eq!(sf.char_count(), 3);
+}
+
+#[test]
+fn test_has_duplicate_line_starts() {
+    let mut sf = SourceFile::new(
+        FileName::Anon(Hash64::ZERO),
+        "ab\ncd\n\nef".to_owned(),
+        SourceFileHashAlgorithm::Sha256,
+    )
+    .unwrap();
+    assert!(!sf.has_duplicate_line_starts());
+
+    // A second line start recorded at the same position, as a buggy lexer might push.
+    sf.lines = FreezeLock::new(SourceFileLines::Lines(vec![
+        RelativeBytePos(0),
+        RelativeBytePos(3),
+        RelativeBytePos(3),
+        RelativeBytePos(7),
+    ]));
+    assert!(sf.has_duplicate_line_starts());
+}
+
+#[test]
+fn test_char_offset_to_bytepos() {
+    let sf = SourceFile::new(
+        FileName::Anon(Hash64::ZERO),
+        "a€b\n€€".to_owned(),
+        SourceFileHashAlgorithm::Sha256,
+    )
+    .unwrap();
+    let offsets: Vec<_> =
+        (0..=6).map(|idx| sf.char_offset_to_bytepos(idx).map(|pos| pos.to_usize())).collect();
+    assert_eq!(offsets, [Some(0), Some(1), Some(4), Some(5), Some(6), Some(9), Some(12)]);
+    assert_eq!(sf.char_offset_to_bytepos(7), None);
+}
+
+#[test]
+fn test_with_line() {
+    let sf = SourceFile::new(
+        FileName::Anon(Hash64::ZERO),
+        "first\n\n€ third\nlast".to_owned(),
+        SourceFileHashAlgorithm::Sha256,
+    )
+    .unwrap();
+    for line in 0..sf.count_lines() {
+        let borrowed = sf.with_line(line, |text| text.to_owned());
+        assert_eq!(borrowed.as_deref(), sf.get_line(line).as_deref());
+    }
+    assert_eq!(sf.with_line(2, str::len), Some(9));
+    assert_eq!(sf.with_line(4, str::len), None);
+}
+
+#[test]
+fn test_multibyte_histogram() {
+    let sf = SourceFile::new(
+        FileName::Anon(Hash64::ZERO),
+        "é € 😀\n€ x".to_owned(),
+        SourceFileHashAlgorithm::Sha256,
+    )
+    .unwrap();
+    assert_eq!(sf.multibyte_histogram(), (1, 2, 1));
+
+    let sf = SourceFile::new(
+        FileName::Anon(Hash64::ZERO),
+        "abc".to_owned(),
+        SourceFileHashAlgorithm::Sha256,
+    )
+    .unwrap();
+    assert_eq!(sf.multibyte_histogram(), (0, 0, 0));
+}
+
+#[test]
+fn test_line_ending_style() {
+    fn style(src: &str) -> Option<LineEnding> {
+        SourceFile::new(
+            FileName::Anon(Hash64::ZERO),
+            src.to_owned(),
+            SourceFileHashAlgorithm::Sha256,
+        )
+        .unwrap()
+        .line_ending_style()
+    }
+
+    assert_eq!(style("a\nb\n"), Some(LineEnding::Lf));
+    assert_eq!(style("a\r\nb\r\n"), Some(LineEnding::CrLf));
+    assert_eq!(style("\u{feff}a\r\nb"), Some(LineEnding::CrLf));
+    assert_eq!(style("a\rb\r"), Some(LineEnding::Cr));
+    assert_eq!(style("a\r\nb\nc"), Some(LineEnding::Mixed));
+    assert_eq!(style("a\r\r\n"), Some(LineEnding::Mixed));
+    assert_eq!(style("abc"), None);
+}
+
+#[test]
+fn test_span_shifted() {
+    let sp = Span::with_root_ctxt(BytePos(10), BytePos(15));
+    assert_eq!(sp.shifted(5), Span::with_root_ctxt(BytePos(15), BytePos(20)));
+    assert_eq!(sp.shifted(-4), Span::with_root_ctxt(BytePos(6), BytePos(11)));
+    assert_eq!(sp.shifted(0), sp);
+    // Endpoints that would underflow are clamped at zero.
+    assert_eq!(sp.shifted(-12), Span::with_root_ctxt(BytePos(0), BytePos(3)));
+    assert_eq!(sp.shifted(-20), Span::with_root_ctxt(BytePos(0), BytePos(0)));
+}
+
+#[test]
+fn test_find_all() {
+    let src = "let x = 1;\nlet y = x + x;\n";
+    let mut sf = SourceFile::new(
+        FileName::Anon(Hash64::ZERO),
+        src.to_owned(),
+        SourceFileHashAlgorithm::Sha256,
+    )
+    .unwrap();
+    // Pretend this file was added after another file.
+    sf.start_pos = BytePos(100);
+
+    let found = sf.find_all("x");
+    assert_eq!(found, [BytePos(104), BytePos(119), BytePos(123)]);
+    for pos in found {
+        let offset = (pos - sf.start_pos).to_usize();
+        assert_eq!(&src[offset..offset + 1], "x");
+    }
+    assert_eq!(sf.find_all("let "), [BytePos(100), BytePos(111)]);
+    assert!(sf.find_all("z").is_empty());
+}
+
+#[test]
+fn test_newline_positions() {
+    let mut sf = SourceFile::new(
+        FileName::Anon(Hash64::ZERO),
+        "fn f() {\n\n    x\r\n}\n".to_owned(),
+        SourceFileHashAlgorithm::Sha256,
+    )
+    .unwrap();
+    sf.start_pos = BytePos(100);
+
+    // The `\r` has been removed, so the source is "fn f() {\n\n    x\n}\n".
+    assert_eq!(sf.newline_positions(), [BytePos(108), BytePos(109), BytePos(115), BytePos(117)]);
+    assert_eq!(sf.newline_positions().len(), sf.count_lines());
+}
+
+#[test]
+fn test_content_hash() {
+    fn content_hash(name: &str, src: &str) -> Option<Hash64> {
+        SourceFile::new(
+            FileName::Custom(name.to_owned()),
+            src.to_owned(),
+            SourceFileHashAlgorithm::Sha256,
+        )
+        .unwrap()
+        .content_hash()
+    }
+    assert_eq!(content_hash("a", "fn main() {}\n"), content_hash("b", "fn main() {}\n"));
+    assert_ne!(content_hash("a", "fn main() {}\n"), content_hash("a", "fn main() { }\n"));
+    // The hash is taken after `\r\n` has been normalized.
+    assert_eq!(content_hash("a", "a\r\nb"), content_hash("a", "a\nb"));
+}